@@ -11,12 +11,19 @@ pub static CAMERA: LazyLock<Arc<RwLock<Box<dyn Camera>>>> =
     LazyLock::new(|| Arc::new(RwLock::new(Box::new(Orbit::new()))));
 pub static RENDERBUFFER: LazyLock<Arc<Mutex<RenderBuffer>>> =
     LazyLock::new(|| Arc::new(Mutex::new(RenderBuffer::new(100, 100))));
-pub static RENDERER: LazyLock<Arc<Box<dyn Renderer>>> =
-    LazyLock::new(|| Arc::new(Box::new(PBR::new())));
-pub static VOXELGRID: LazyLock<Arc<RwLock<VoxelGrid>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(VoxelGrid::default())));
-pub static PALETTE: LazyLock<Arc<RwLock<Palette>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(Palette::default())));
+pub static RENDERER: LazyLock<RwLock<RendererRegistry>> =
+    LazyLock::new(|| RwLock::new(RendererRegistry::new()));
+// Copied on write with `Arc::make_mut`, render passes keep the version they were submitted with.
+pub static VOXELGRID: LazyLock<RwLock<Arc<VoxelGrid>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(VoxelGrid::default())));
+pub static PALETTE: LazyLock<RwLock<Arc<Palette>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(Palette::default())));
+pub static SCENE: LazyLock<RwLock<Arc<Scene>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(Scene::default())));
+pub static RENDERSETTINGS: LazyLock<Arc<RwLock<RenderSettings>>> =
+    LazyLock::new(|| Arc::new(RwLock::new(RenderSettings::default())));
+pub static RENDERSERVICE: LazyLock<RenderService> =
+    LazyLock::new(|| RenderService::new(num_cpus::get()));
 
 pub static MODELEDITOR: LazyLock<RwLock<ModelEditor>> =
     LazyLock::new(|| RwLock::new(ModelEditor::new()));
@@ -69,7 +76,7 @@ pub static SCENEMANAGER: LazyLock<RwLock<SceneManager>> =
 pub struct Editor {
     event_receiver: Option<Receiver<TheEvent>>,
 
-    project: Project,
+    project_path: Option<PathBuf>,
     context: Context,
     update_tracker: UpdateTracker,
     /*
@@ -98,7 +105,6 @@ impl TheTrait for Editor {
     where
         Self: Sized,
     {
-        let project = Project::default();
         let context = Context::default();
 
         //let (self_update_tx, self_update_rx) = channel();
@@ -118,7 +124,7 @@ impl TheTrait for Editor {
         */
         Self {
             event_receiver: None,
-            project,
+            project_path: None,
             context,
             update_tracker: UpdateTracker::new(),
             /*
@@ -145,11 +151,12 @@ impl TheTrait for Editor {
 
     fn init(&mut self, _ctx: &mut TheContext) {
         let mut grid = VOXELGRID.write().unwrap();
+        let grid = Arc::make_mut(&mut grid);
         let bottom = -((grid.bounds[1] / 2.0) as i32);
 
         for (index, tile) in &mut grid.tiles {
             if index.1 == bottom {
-                tile.add_floor();
+                Arc::make_mut(tile).add_floor();
             }
         }
 
@@ -162,7 +169,7 @@ impl TheTrait for Editor {
                 if let Some(bytes) = Embedded::get(name) {
                     if let Ok(string) = std::str::from_utf8(bytes.data.as_ref()) {
                         let mut palette = PALETTE.write().unwrap();
-                        let _ = palette.load_paintnet(string);
+                        let _ = Arc::make_mut(&mut palette).load_paintnet(string);
                     }
                 }
             }
@@ -267,6 +274,47 @@ impl TheTrait for Editor {
             TheId::named("Save As"),
             TheAccelerator::new(TheAcceleratorKey::CTRLCMD, 'a'),
         ));
+        file_menu.add_separator();
+        file_menu.add(TheContextMenuItem::new(
+            str!("Import MagicaVoxel..."),
+            TheId::named("Import Vox"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Export MagicaVoxel..."),
+            TheId::named("Export Vox"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Import Qubicle..."),
+            TheId::named("Import Qubicle"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Export Qubicle..."),
+            TheId::named("Export Qubicle"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Import Heightmap..."),
+            TheId::named("Import Heightmap"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Import PNG Slices..."),
+            TheId::named("Import Slices"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Export Mesh..."),
+            TheId::named("Export Mesh"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Export Mesh (1 Voxel = 1 Unit)..."),
+            TheId::named("Export Mesh Voxel Units"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Export STL..."),
+            TheId::named("Export STL"),
+        ));
+        file_menu.add(TheContextMenuItem::new(
+            str!("Export STL (ASCII)..."),
+            TheId::named("Export STL ASCII"),
+        ));
         let mut edit_menu = TheContextMenu::named(str!("Edit"));
         edit_menu.add(TheContextMenuItem::new_with_accel(
             str!("Undo"),
@@ -294,6 +342,98 @@ impl TheTrait for Editor {
             TheId::named("Paste"),
             TheAccelerator::new(TheAcceleratorKey::CTRLCMD, 'v'),
         ));
+        let mut render_menu = TheContextMenu::named(str!("Render"));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Export PNG (sRGB)..."),
+            TheId::named("Export Render sRGB"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Export PNG (Filmic)..."),
+            TheId::named("Export Render Filmic"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Export PNG (Linear)..."),
+            TheId::named("Export Render Linear"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Export HDR..."),
+            TheId::named("Export Render HDR"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Export Passes (EXR)..."),
+            TheId::named("Export Passes"),
+        ));
+        render_menu.add_separator();
+        render_menu.add(TheContextMenuItem::new(
+            str!("Beauty"),
+            TheId::named("View Pass: Beauty"),
+        ));
+        for aov in Aov::ALL {
+            render_menu.add(TheContextMenuItem::new(
+                aov.name().to_string(),
+                TheId::named(&format!("View Pass: {}", aov.name())),
+            ));
+        }
+        render_menu.add_separator();
+        render_menu.add(TheContextMenuItem::new(
+            str!("Load Environment Map..."),
+            TheId::named("Load Environment"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Procedural Sky"),
+            TheId::named("Procedural Sky"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Solid Background"),
+            TheId::named("Solid Background"),
+        ));
+        render_menu.add_separator();
+        render_menu.add(TheContextMenuItem::new(
+            str!("Rotate Environment Left"),
+            TheId::named("Rotate Environment Left"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Rotate Environment Right"),
+            TheId::named("Rotate Environment Right"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Raise Sun"),
+            TheId::named("Raise Sun"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Lower Sun"),
+            TheId::named("Lower Sun"),
+        ));
+        render_menu.add_separator();
+        render_menu.add(TheContextMenuItem::new(
+            str!("Toggle Fog"),
+            TheId::named("Toggle Fog"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Denser Fog"),
+            TheId::named("Denser Fog"),
+        ));
+        render_menu.add(TheContextMenuItem::new(
+            str!("Thinner Fog"),
+            TheId::named("Thinner Fog"),
+        ));
+        render_menu.add_separator();
+        render_menu.add(TheContextMenuItem::new(
+            str!("Toggle Denoiser"),
+            TheId::named("Toggle Denoiser"),
+        ));
+        render_menu.add_separator();
+        for name in RENDERER.read().unwrap().names() {
+            render_menu.add(TheContextMenuItem::new(
+                name.clone(),
+                TheId::named(&format!("Renderer: {}", name)),
+            ));
+        }
+        render_menu.add_separator();
+        render_menu.add(TheContextMenuItem::new(
+            str!("Render Settings..."),
+            TheId::named("Render Settings"),
+        ));
         // let mut view_menu = TheContextMenu::named(str!("View"));
         // view_menu.add(TheContextMenuItem::new_with_accel(
         //     str!("2D Map"),
@@ -324,6 +464,7 @@ impl TheTrait for Editor {
 
         menu.add_context_menu(file_menu);
         menu.add_context_menu(edit_menu);
+        menu.add_context_menu(render_menu);
         menu_canvas.set_widget(menu);
 
         // Menubar
@@ -607,8 +748,402 @@ impl TheTrait for Editor {
                             ));
                         }
                     }
+                    TheEvent::FileRequesterResult(id, paths) => {
+                        if id.name == "Open" {
+                            for p in paths {
+                                match Project::load(p) {
+                                    Ok(project) => {
+                                        let environment = crate::utils::project_to_scene(project);
+                                        self.project_path = Some(p.clone());
+
+                                        crate::utils::update_palette_ui(ui, ctx);
+                                        ctx.ui.send(TheEvent::PaletteIndexChanged(
+                                            TheId::named("PalettePicker"),
+                                            0,
+                                        ));
+                                        let status = match environment {
+                                            Ok(()) => "Project loaded successfully.".to_string(),
+                                            Err(err) => format!(
+                                                "Project loaded, unable to load the environment map: {}",
+                                                err
+                                            ),
+                                        };
+                                        ctx.ui
+                                            .send(TheEvent::SetStatusText(TheId::empty(), status));
+                                        redraw = true;
+                                    }
+                                    Err(err) => {
+                                        ctx.ui.send(TheEvent::SetStatusText(
+                                            TheId::empty(),
+                                            format!("Unable to load project: {}", err),
+                                        ));
+                                    }
+                                }
+                            }
+                        } else if id.name == "Import Vox" {
+                            for p in paths {
+                                let result = std::fs::read(p).and_then(|data| {
+                                    crate::voxel::io::vox::import_vox(
+                                        &data,
+                                        Arc::make_mut(&mut VOXELGRID.write().unwrap()),
+                                        Arc::make_mut(&mut PALETTE.write().unwrap()),
+                                    )
+                                });
+                                match result {
+                                    Ok(()) => {
+                                        crate::utils::reset_render();
+                                        crate::utils::update_palette_ui(ui, ctx);
+                                        ctx.ui.send(TheEvent::SetStatusText(
+                                            TheId::empty(),
+                                            "MagicaVoxel file imported successfully.".to_string(),
+                                        ));
+                                        redraw = true;
+                                    }
+                                    Err(err) => {
+                                        ctx.ui.send(TheEvent::SetStatusText(
+                                            TheId::empty(),
+                                            format!("Unable to import MagicaVoxel file: {}", err),
+                                        ));
+                                    }
+                                }
+                            }
+                        } else if id.name == "Import Qubicle" {
+                            for p in paths {
+                                match crate::utils::import_qubicle(p) {
+                                    Ok(()) => {
+                                        crate::utils::reset_render();
+                                        crate::utils::update_palette_ui(ui, ctx);
+                                        ctx.ui.send(TheEvent::SetStatusText(
+                                            TheId::empty(),
+                                            "Qubicle file imported successfully.".to_string(),
+                                        ));
+                                        redraw = true;
+                                    }
+                                    Err(err) => {
+                                        ctx.ui.send(TheEvent::SetStatusText(
+                                            TheId::empty(),
+                                            format!("Unable to import Qubicle file: {}", err),
+                                        ));
+                                    }
+                                }
+                            }
+                        } else if id.name == "Import Heightmap" || id.name == "Import Slices" {
+                            for p in paths {
+                                let result = if id.name == "Import Heightmap" {
+                                    crate::utils::import_heightmap(p)
+                                } else {
+                                    crate::utils::import_slices(p)
+                                };
+                                match result {
+                                    Ok(()) => {
+                                        crate::utils::reset_render();
+                                        ctx.ui.send(TheEvent::SetStatusText(
+                                            TheId::empty(),
+                                            "PNG imported successfully.".to_string(),
+                                        ));
+                                        redraw = true;
+                                    }
+                                    Err(err) => {
+                                        ctx.ui.send(TheEvent::SetStatusText(
+                                            TheId::empty(),
+                                            format!("Unable to import PNG: {}", err),
+                                        ));
+                                    }
+                                }
+                            }
+                        } else if id.name == "Load Environment" {
+                            for p in paths {
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match crate::utils::load_environment(p) {
+                                        Ok(()) => "Environment map loaded.".to_string(),
+                                        Err(err) => {
+                                            format!("Unable to load environment map: {}", err)
+                                        }
+                                    },
+                                ));
+                            }
+                        } else if id.name == "Export Render HDR" {
+                            for p in paths {
+                                let mut buffer = RENDERBUFFER.lock().unwrap();
+                                let frame = crate::utils::final_frame(&buffer);
+                                let result =
+                                    if p.extension().is_some_and(|e| e.eq_ignore_ascii_case("hdr"))
+                                    {
+                                        frame.save_hdr(p)
+                                    } else {
+                                        frame.save_exr(p)
+                                    };
+                                if result.is_ok() {
+                                    buffer.file_path = Some(p.clone());
+                                }
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match result {
+                                        Ok(()) => "Render exported successfully.".to_string(),
+                                        Err(err) => format!("Unable to export render: {}", err),
+                                    },
+                                ));
+                            }
+                        } else if id.name == "Export Passes" {
+                            for p in paths {
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match crate::utils::export_passes(p) {
+                                        Ok(()) => "Passes exported successfully.".to_string(),
+                                        Err(err) => format!("Unable to export passes: {}", err),
+                                    },
+                                ));
+                            }
+                        } else if id.name.starts_with("Export Render") {
+                            let mapping = match id.name.as_str() {
+                                "Export Render Filmic" => ToneMapping::Filmic,
+                                "Export Render Linear" => ToneMapping::Linear,
+                                _ => ToneMapping::Srgb,
+                            };
+                            for p in paths {
+                                let mut buffer = RENDERBUFFER.lock().unwrap();
+                                let result =
+                                    crate::utils::final_frame(&buffer).save_png(p, mapping);
+                                if result.is_ok() {
+                                    buffer.file_path = Some(p.clone());
+                                }
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match result {
+                                        Ok(()) => "Render exported successfully.".to_string(),
+                                        Err(err) => format!("Unable to export render: {}", err),
+                                    },
+                                ));
+                            }
+                        } else if id.name == "Export Qubicle" {
+                            for p in paths {
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match crate::utils::export_qubicle(p) {
+                                        Ok(()) => "Qubicle file exported successfully.".to_string(),
+                                        Err(err) => {
+                                            format!("Unable to export Qubicle file: {}", err)
+                                        }
+                                    },
+                                ));
+                            }
+                        } else if id.name == "Export Vox" {
+                            for p in paths {
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match crate::utils::export_vox(p) {
+                                        Ok(()) => {
+                                            "MagicaVoxel file exported successfully.".to_string()
+                                        }
+                                        Err(err) => {
+                                            format!("Unable to export MagicaVoxel file: {}", err)
+                                        }
+                                    },
+                                ));
+                            }
+                        } else if id.name == "Export Mesh" || id.name == "Export Mesh Voxel Units" {
+                            for p in paths {
+                                let voxel_units = id.name == "Export Mesh Voxel Units";
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match crate::utils::export_mesh(p, voxel_units) {
+                                        Ok(()) => "Mesh exported successfully.".to_string(),
+                                        Err(err) => format!("Unable to export mesh: {}", err),
+                                    },
+                                ));
+                            }
+                        } else if id.name == "Export STL" || id.name == "Export STL ASCII" {
+                            for p in paths {
+                                let ascii = id.name == "Export STL ASCII";
+                                ctx.ui.send(TheEvent::SetStatusText(
+                                    TheId::empty(),
+                                    match crate::utils::export_stl(p, ascii) {
+                                        Ok(()) => "STL exported successfully.".to_string(),
+                                        Err(err) => format!("Unable to export STL: {}", err),
+                                    },
+                                ));
+                            }
+                        } else if id.name == "Save As" {
+                            for p in paths {
+                                let project = crate::utils::scene_to_project();
+                                if project.save(p).is_ok() {
+                                    self.project_path = Some(p.clone());
+                                    ctx.ui.send(TheEvent::SetStatusText(
+                                        TheId::empty(),
+                                        "Project saved successfully.".to_string(),
+                                    ))
+                                } else {
+                                    ctx.ui.send(TheEvent::SetStatusText(
+                                        TheId::empty(),
+                                        "Unable to save project!".to_string(),
+                                    ))
+                                }
+                            }
+                        }
+                    }
                     TheEvent::StateChanged(id, _) => {
-                        if id.name == "Palette Mode" {
+                        if id.name == "Open" {
+                            ctx.ui.open_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Open".into(),
+                                TheFileExtension::new("Shape-Z".into(), vec!["shapez".to_string()]),
+                            );
+                            ctx.ui
+                                .set_widget_state("Open".to_string(), TheWidgetState::None);
+                            ctx.ui.clear_hover();
+                            redraw = true;
+                        } else if id.name == "Save" {
+                            if let Some(path) = &self.project_path {
+                                let project = crate::utils::scene_to_project();
+                                if project.save(path).is_ok() {
+                                    ctx.ui.send(TheEvent::SetStatusText(
+                                        TheId::empty(),
+                                        "Project saved successfully.".to_string(),
+                                    ));
+                                } else {
+                                    ctx.ui.send(TheEvent::SetStatusText(
+                                        TheId::empty(),
+                                        "Unable to save project!".to_string(),
+                                    ))
+                                }
+                            } else {
+                                ctx.ui.send(TheEvent::StateChanged(
+                                    TheId::named("Save As"),
+                                    TheWidgetState::Clicked,
+                                ));
+                            }
+                            ctx.ui
+                                .set_widget_state("Save".to_string(), TheWidgetState::None);
+                        } else if id.name == "Save As" {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Save".into(),
+                                TheFileExtension::new("Shape-Z".into(), vec!["shapez".to_string()]),
+                            );
+                            ctx.ui
+                                .set_widget_state("Save As".to_string(), TheWidgetState::None);
+                            ctx.ui.clear_hover();
+                            redraw = true;
+                        } else if id.name == "Import Vox" {
+                            ctx.ui.open_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Import".into(),
+                                TheFileExtension::new(
+                                    "MagicaVoxel".into(),
+                                    vec!["vox".to_string()],
+                                ),
+                            );
+                        } else if id.name == "Export Vox" {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Export".into(),
+                                TheFileExtension::new(
+                                    "MagicaVoxel".into(),
+                                    vec!["vox".to_string()],
+                                ),
+                            );
+                        } else if id.name == "Export Mesh" || id.name == "Export Mesh Voxel Units" {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Export".into(),
+                                TheFileExtension::new(
+                                    "Mesh".into(),
+                                    vec!["obj".to_string(), "ply".to_string(), "glb".to_string()],
+                                ),
+                            );
+                        } else if id.name == "Export STL" || id.name == "Export STL ASCII" {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Export".into(),
+                                TheFileExtension::new("STL".into(), vec!["stl".to_string()]),
+                            );
+                        } else if id.name == "Import Qubicle" {
+                            ctx.ui.open_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Import".into(),
+                                TheFileExtension::new(
+                                    "Qubicle".into(),
+                                    vec!["qb".to_string(), "qbt".to_string()],
+                                ),
+                            );
+                        } else if id.name == "Import Heightmap" || id.name == "Import Slices" {
+                            // For slices any PNG of the stack can be picked, the numbered
+                            // files with the same name next to it are imported with it
+                            ctx.ui.open_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Import".into(),
+                                TheFileExtension::new("PNG".into(), vec!["png".to_string()]),
+                            );
+                        } else if id.name == "Load Environment" {
+                            ctx.ui.open_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Load".into(),
+                                TheFileExtension::new("HDR".into(), vec!["hdr".to_string()]),
+                            );
+                        } else if id.name == "Rotate Environment Left" {
+                            crate::utils::rotate_environment(-crate::F_PI / 8.0);
+                        } else if id.name == "Rotate Environment Right" {
+                            crate::utils::rotate_environment(crate::F_PI / 8.0);
+                        } else if id.name == "Procedural Sky" {
+                            crate::utils::set_background(Background::Sky(Sky::default()));
+                        } else if id.name == "Solid Background" {
+                            crate::utils::set_background(Background::default());
+                        } else if id.name == "Raise Sun" {
+                            crate::utils::raise_sun(crate::F_PI / 36.0);
+                        } else if id.name == "Lower Sun" {
+                            crate::utils::raise_sun(-crate::F_PI / 36.0);
+                        } else if id.name == "Toggle Fog" {
+                            crate::utils::toggle_fog();
+                        } else if id.name == "Denser Fog" {
+                            crate::utils::scale_fog(1.5);
+                        } else if id.name == "Thinner Fog" {
+                            crate::utils::scale_fog(1.0 / 1.5);
+                        } else if let Some(name) = id.name.strip_prefix("Renderer: ") {
+                            crate::utils::set_renderer(name);
+                            // Rebuild the tool params with the parameters of the renderer
+                            let mut toollist = TOOLLIST.write().unwrap();
+                            let id = toollist.get_current_tool().id().uuid;
+                            toollist.set_tool(id, ui, ctx, &mut self.context);
+                        } else if id.name == "Toggle Denoiser" {
+                            crate::utils::toggle_denoiser();
+                            MODELEDITOR.write().unwrap().refresh();
+                        } else if let Some(name) = id.name.strip_prefix("View Pass: ") {
+                            let pass = Aov::ALL.into_iter().find(|aov| aov.name() == name);
+                            MODELEDITOR.write().unwrap().set_pass(pass);
+                        } else if id.name == "Render Settings" {
+                            MODELEDITOR.read().unwrap().set_settings_ui(ui, ctx);
+                        } else if id.name == "Export Render HDR" {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Export".into(),
+                                TheFileExtension::new(
+                                    "HDR".into(),
+                                    vec!["exr".to_string(), "hdr".to_string()],
+                                ),
+                            );
+                        } else if id.name == "Export Passes" {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Export".into(),
+                                TheFileExtension::new("EXR".into(), vec!["exr".to_string()]),
+                            );
+                        } else if id.name.starts_with("Export Render") {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Export".into(),
+                                TheFileExtension::new("PNG".into(), vec!["png".to_string()]),
+                            );
+                        } else if id.name == "Export Qubicle" {
+                            ctx.ui.save_file_requester(
+                                TheId::named_with_id(id.name.as_str(), Uuid::new_v4()),
+                                "Export".into(),
+                                TheFileExtension::new(
+                                    "Qubicle".into(),
+                                    vec!["qb".to_string(), "qbt".to_string()],
+                                ),
+                            );
+                        } else if id.name == "Palette Mode" {
                             self.context.mode = ToolMode::Palette;
                             ctx.ui
                                 .set_widget_state("Point Mode".into(), TheWidgetState::None);
@@ -637,7 +1172,7 @@ impl TheTrait for Editor {
         let (redraw_update, tick_update) = self.update_tracker.update((1000 / 30) as u64, 250_u64);
 
         if redraw_update {
-            MODELEDITOR.write().unwrap().draw(ui);
+            MODELEDITOR.write().unwrap().draw(ui, ctx);
 
             redraw = true;
         }
@@ -47,17 +47,29 @@ pub mod prelude {
     pub use crate::toollist::*;
     pub use crate::{Color, F};
 
-    pub use crate::voxel::camera::Camera;
+    pub use crate::voxel::aov::{Aov, AovSample};
+    pub use crate::voxel::background::{Background, BackgroundProvider};
     pub use crate::voxel::camera::iso::Iso;
     pub use crate::voxel::camera::orbit::Orbit;
     pub use crate::voxel::camera::pinhole::Pinhole;
+    pub use crate::voxel::camera::{Camera, CameraState};
+    pub use crate::voxel::denoise::Denoiser;
+    pub use crate::voxel::emitters::{EmissiveVoxel, Emitters};
+    pub use crate::voxel::environment::Environment;
+    pub use crate::voxel::fog::Fog;
     pub use crate::voxel::grid::VoxelGrid;
+    pub use crate::voxel::light::{Light, LightSample, LightType};
     pub use crate::voxel::palette::{Material, Palette};
     pub use crate::voxel::ray::Ray;
-    pub use crate::voxel::renderbuffer::RenderBuffer;
-    pub use crate::voxel::renderer::Renderer;
-    // pub use crate::voxel::renderer::editshader::EditShader;
+    pub use crate::voxel::renderbuffer::{RenderBuffer, ToneMapping};
+    pub use crate::voxel::renderer::editshader::EditShader;
     pub use crate::voxel::renderer::pbr::PBR;
+    pub use crate::voxel::renderer::registry::RendererRegistry;
+    pub use crate::voxel::renderer::service::{RenderService, RenderSnapshot, RenderTile};
+    pub use crate::voxel::renderer::settings::RenderSettings;
+    pub use crate::voxel::renderer::{Renderer, RendererParam};
+    pub use crate::voxel::scene::Scene;
+    pub use crate::voxel::sky::Sky;
     pub use crate::voxel::tile::Tile;
     pub use crate::voxel::{Coord, Face, HitRecord, HitType};
 
@@ -1,13 +1,39 @@
 use crate::prelude::*;
-use crate::utils::reset_render;
-use std::sync::{Arc, Mutex};
+use crate::utils::{denoise_enabled, reset_render};
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, channel};
 use std::thread;
 use vek::Vec2;
 
-use crate::editor::{CAMERA, PALETTE, RENDERBUFFER, RENDERER, VOXELGRID};
+use crate::editor::{
+    CAMERA, PALETTE, RENDERBUFFER, RENDERER, RENDERSERVICE, RENDERSETTINGS, SCENE, VOXELGRID,
+};
+
+/// The size of the tiles the frame is split into for rendering and adaptive sampling.
+const TILE_SIZE: usize = 80;
+
+/// The most samples a noisy tile gets in one pass.
+const MAX_TILE_SAMPLES: u32 = 4;
 
 pub struct ModelEditor {
     drag_coord: Vec2<i32>,
+
+    /// The generation of the render service the accumulation belongs to.
+    generation: u64,
+    /// The tile samples of the current pass which are still being rendered.
+    pending: usize,
+    /// The time the current accumulation started, in ms.
+    render_start: u128,
+    /// True once the accumulation reached the targets of the render settings.
+    finished: bool,
+    /// The AOV pass shown instead of the beauty pass.
+    pass: Option<Aov>,
+    /// Blit the frame even without new tiles, after the shown pass changed.
+    refresh: bool,
+    /// The grid and palette the emitters of the scene are collected from.
+    emitters_source: Option<(Arc<VoxelGrid>, Arc<Palette>)>,
+    /// Receives the emitters of `emitters_source` once collected in the background.
+    emitters_receiver: Option<Receiver<Emitters>>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -15,149 +41,211 @@ impl ModelEditor {
     pub fn new() -> Self {
         Self {
             drag_coord: Vec2::zero(),
+            generation: u64::MAX,
+            pending: 0,
+            render_start: 0,
+            finished: false,
+            pass: None,
+            refresh: false,
+            emitters_source: None,
+            emitters_receiver: None,
         }
     }
 
-    pub fn draw(&mut self, ui: &mut TheUI) {
+    /// Collect the tiles finished by the render service, start the next pass once all tiles
+    /// of the current one are in and blit the accumulated frame. Never waits for the workers,
+    /// the render buffer is only touched on this thread.
+    pub fn draw(&mut self, ui: &mut TheUI, ctx: &mut TheContext) {
         if let Some(render_view) = ui.get_render_view("ModelView") {
             let dim = *render_view.dim();
             let surface = render_view.render_buffer_mut();
             surface.resize(dim.width, dim.height);
 
-            let mut rb = Arc::clone(&RENDERBUFFER);
+            let settings = *RENDERSETTINGS.read().unwrap();
+            let mut buffer = RENDERBUFFER.lock().unwrap();
 
             // Resize if needed
-            {
-                let mut buffer = rb.lock().unwrap();
-                if buffer.width != dim.width as usize || buffer.height != dim.height as usize {
-                    *buffer = RenderBuffer::new(dim.width as usize, dim.height as usize);
-                    buffer.accum = 1;
-                }
+            if buffer.width != dim.width as usize || buffer.height != dim.height as usize {
+                *buffer = RenderBuffer::new(dim.width as usize, dim.height as usize);
+                RENDERSERVICE.cancel();
             }
 
-            let grid = Arc::clone(&VOXELGRID);
-            let palette = Arc::clone(&PALETTE);
-            let renderer = Arc::clone(&RENDERER);
-            let camera = Arc::clone(&CAMERA);
+            // The accumulation restarts after every change
+            let generation = RENDERSERVICE.generation();
+            if generation != self.generation {
+                self.generation = generation;
+                self.pending = 0;
+                self.render_start = self.get_time();
+                self.finished = false;
+            }
 
-            // Render
-            self.render(&mut rb, &grid, &palette, &renderer, &camera);
+            // The next pass samples the emitters once they are collected
+            self.update_emitters();
+            if let Some(receiver) = &self.emitters_receiver
+                && let Ok(emitters) = receiver.try_recv()
             {
-                let mut buffer = rb.lock().unwrap();
-                buffer.accum += 1;
+                Arc::make_mut(&mut SCENE.write().unwrap()).emitters = emitters;
+                self.emitters_receiver = None;
+            }
+
+            let mut updated = false;
+            for result in RENDERSERVICE.finished() {
+                buffer.accum_from(result.tile.x, result.tile.y, &result.buffer);
+                self.pending = self.pending.saturating_sub(1);
+                updated = true;
+            }
+            let pass_done = updated && self.pending == 0;
+
+            if self.pending == 0 && !self.finished {
+                let renderer = RENDERER.read().unwrap().active();
+                let (tiles, progress) =
+                    self.schedule_tiles(&buffer, &settings, renderer.progressive());
+
+                // Once all tiles are converged the editor stays idle until the next change
+                if tiles.is_empty() {
+                    self.finished = true;
+                    let seconds = (self.get_time() - self.render_start) as F / 1000.0;
+                    ctx.ui.send(TheEvent::SetStatusText(
+                        TheId::empty(),
+                        format!(
+                            "Render finished: {:.0} samples per pixel in {:.1}s.",
+                            progress.0, seconds
+                        ),
+                    ));
+                } else {
+                    ctx.ui.send(TheEvent::SetStatusText(
+                        TheId::empty(),
+                        format!(
+                            "Rendering: {:.0} samples per pixel, {:.0}% done.",
+                            progress.0,
+                            progress.1 * 100.0
+                        ),
+                    ));
+
+                    let snapshot = Arc::new(RenderSnapshot {
+                        grid: Arc::clone(&VOXELGRID.read().unwrap()),
+                        palette: Arc::clone(&PALETTE.read().unwrap()),
+                        scene: Arc::clone(&SCENE.read().unwrap()),
+                        renderer,
+                        camera: CAMERA.read().unwrap().state().to_camera(),
+                        settings,
+                        width: buffer.width,
+                        height: buffer.height,
+                    });
+                    self.pending = RENDERSERVICE.submit(snapshot, tiles);
+                    buffer.accum += 1;
+                }
             }
 
             // Blit
-            {
-                let buffer = rb.lock().unwrap();
+            if let Some(pass) = self.pass {
+                if updated || self.refresh {
+                    surface
+                        .pixels_mut()
+                        .copy_from_slice(&buffer.aov_preview(pass).to_u8_vec());
+                }
+            } else if denoise_enabled() {
+                // Tiles of a pass in progress have fewer samples, only denoise complete passes
+                if pass_done || self.refresh {
+                    Denoiser::default()
+                        .denoise(&buffer)
+                        .to_u8_vec_gamma_buffer(surface.pixels_mut());
+                }
+            } else if updated || self.refresh {
                 buffer.to_u8_vec_gamma_buffer(surface.pixels_mut());
             }
+            self.refresh = false;
         }
     }
 
-    pub fn render(
-        &self,
-        buffer: &mut Arc<Mutex<RenderBuffer>>,
-        grid: &Arc<RwLock<VoxelGrid>>,
-        palette: &Arc<RwLock<Palette>>,
-        renderer: &Arc<Box<dyn Renderer>>,
-        camera: &Arc<RwLock<Box<dyn Camera>>>,
-    ) {
-        let tile_size = (80, 80);
-
-        let width = buffer.lock().unwrap().width;
-        let height = buffer.lock().unwrap().height;
-
-        let tiles = self.create_tiles(width, height, tile_size.0, tile_size.1);
-        let screen_size = Vec2::new(width as F, height as F);
-        let tiles_mutex = Arc::new(Mutex::new(tiles));
-
-        let num_cpus = num_cpus::get();
-        let _start = self.get_time();
-
-        // let ft_arc = Arc::clone(&ft);
-        let grid_arc = Arc::clone(grid);
-        let palette_arc = Arc::clone(palette);
-        let renderer_arc = Arc::clone(renderer);
-        // let camera_arc = Arc::clone(camera);
-
-        // Create threads
-        let mut handles = vec![];
-        for _ in 0..num_cpus {
-            // let ft = Arc::clone(&ft_arc);
-            let grid = Arc::clone(&grid_arc);
-            let palette = Arc::clone(&palette_arc);
-            let renderer = Arc::clone(&renderer_arc);
-            let camera = Arc::clone(camera);
-
-            let tiles_mutex = Arc::clone(&tiles_mutex);
-            let buffer_mutex = Arc::clone(buffer);
-
-            let handle = thread::spawn(move || {
-                let mut tile_buffer = RenderBuffer::new(tile_size.0, tile_size.1);
-                loop {
-                    // Lock mutex to access tiles
-                    let mut tiles = tiles_mutex.lock().unwrap();
-
-                    // Check if there are remaining tiles
-                    if let Some(tile) = tiles.pop() {
-                        // Release mutex before processing tile
-                        drop(tiles);
-
-                        let grid_guard = grid.read().unwrap();
-                        let grid_ref: &VoxelGrid = &grid_guard;
-                        let palette_guard = palette.read().unwrap();
-                        let palette_ref: &Palette = &palette_guard;
-                        let camera_guard = camera.read().unwrap();
-                        let camera_ref = &camera_guard;
-
-                        // Process tile
-                        for h in 0..tile.height {
-                            for w in 0..tile.width {
-                                let x = tile.x + w;
-                                let y = tile.y + h;
-
-                                if x >= width || y >= height {
-                                    continue;
-                                }
+    /// Collect the emissive voxels again in the background if the grid or the palette
+    /// changed. Until they are in the scene has no emitters, which only adds noise.
+    fn update_emitters(&mut self) {
+        let grid = Arc::clone(&VOXELGRID.read().unwrap());
+        let palette = Arc::clone(&PALETTE.read().unwrap());
+        if let Some((source_grid, source_palette)) = &self.emitters_source
+            && source_grid.shares_tiles(&grid)
+            && Arc::ptr_eq(source_palette, &palette)
+        {
+            return;
+        }
 
-                                let uv = Vec2::new(
-                                    x as F / screen_size.x,
-                                    1.0 - (y as F / screen_size.y),
-                                );
+        Arc::make_mut(&mut SCENE.write().unwrap()).emitters = Emitters::default();
+        self.emitters_source = Some((Arc::clone(&grid), Arc::clone(&palette)));
 
-                                let p = renderer.render(
-                                    uv,
-                                    screen_size,
-                                    grid_ref,
-                                    palette_ref,
-                                    camera_ref,
-                                );
-                                tile_buffer.set(w, h, p.into_array());
-                                // tile_buffer.set(w, h, [uv.x, uv.y, 0.0, 1.0]);
-                            }
-                        }
-                        // Save the tile buffer to the main buffer
-                        buffer_mutex
-                            .lock()
-                            .unwrap()
-                            .accum_from(tile.x, tile.y, &tile_buffer);
-                    } else {
-                        // No remaining tiles, exit loop
-                        break;
-                    }
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            // The receiver is gone if the grid changed again in the meantime
+            _ = sender.send(Emitters::build(&grid, &palette));
+        });
+        self.emitters_receiver = Some(receiver);
+    }
+
+    /// Blit the frame again with the next draw.
+    pub fn refresh(&mut self) {
+        self.refresh = true;
+    }
+
+    /// Show an AOV pass in the model view, None shows the beauty pass.
+    pub fn set_pass(&mut self, pass: Option<Aov>) {
+        self.pass = pass;
+        self.refresh = true;
+    }
+
+    /// The tiles which need more samples, with the number of samples of this pass. Tiles
+    /// reach the sample target of the settings or stop early once all their pixels are
+    /// below the noise threshold, noisy tiles get up to `MAX_TILE_SAMPLES` per pass. A
+    /// renderer which is not progressive only gets a single sample. Also returns the mean
+    /// samples per pixel and the share of finished tiles.
+    fn schedule_tiles(
+        &self,
+        buffer: &RenderBuffer,
+        settings: &RenderSettings,
+        progressive: bool,
+    ) -> (Vec<RenderTile>, (F, F)) {
+        let mut tiles = self.create_tiles(buffer.width, buffer.height, TILE_SIZE, TILE_SIZE);
+        let total = tiles.len();
+
+        tiles.retain_mut(|tile| {
+            let samples = buffer.samples[tile.y * buffer.width + tile.x];
+            let remaining = if !progressive {
+                1_u32.saturating_sub(samples)
+            } else if settings.samples > 0 {
+                settings.samples.saturating_sub(samples)
+            } else {
+                u32::MAX
+            };
+            if remaining == 0 {
+                return false;
+            }
+
+            let mut count = 1;
+            if settings.noise_threshold > 0.0 && samples >= settings.min_samples.max(2) {
+                let noise = buffer.max_noise(tile.x, tile.y, tile.width, tile.height);
+                if noise <= settings.noise_threshold {
+                    return false;
                 }
-            });
-            handles.push(handle);
-        }
+                count =
+                    ((noise / settings.noise_threshold).ceil() as u32).clamp(1, MAX_TILE_SAMPLES);
+            }
 
-        // Wait for all threads to finish
-        for handle in handles {
-            handle.join().unwrap();
-        }
+            tile.sample = samples;
+            tile.count = count.min(remaining);
+            true
+        });
+
+        let spp = if buffer.samples.is_empty() {
+            0.0
+        } else {
+            buffer.samples.iter().map(|s| *s as F).sum::<F>() / buffer.samples.len() as F
+        };
+        let done = if total > 0 {
+            (total - tiles.len()) as F / total as F
+        } else {
+            1.0
+        };
 
-        let _stop = self.get_time();
-        // println!("Shader execution time: {:?} ms.", _stop - _start);
+        (tiles, (spp, done))
     }
 
     pub fn handle_event(
@@ -172,9 +260,8 @@ impl ModelEditor {
             TheEvent::Copy => {}
             TheEvent::RenderViewClicked(id, _) => {
                 if id.name == "ModelView" {
-                    let grid = Arc::clone(&VOXELGRID);
-                    let mut grid = grid.write().unwrap();
-                    grid.merge_preview();
+                    Arc::make_mut(&mut VOXELGRID.write().unwrap()).merge_preview();
+                    reset_render();
                 }
             }
             TheEvent::RenderViewHoverChanged(id, coord) => {
@@ -202,8 +289,8 @@ impl ModelEditor {
                             camera.rotate((*coord - self.drag_coord).map(|v| -v as f32 * 2.0));
                             self.drag_coord = *coord;
                         } else {
-                            let grid = Arc::clone(&VOXELGRID);
-                            let mut grid = grid.write().unwrap();
+                            let mut grid = VOXELGRID.write().unwrap();
+                            let grid = Arc::make_mut(&mut grid);
 
                             grid.preview = None;
                             let hit = grid.dda(&ray);
@@ -238,7 +325,7 @@ impl ModelEditor {
                                     }
                                 }
                                 preview.update_bboxes();
-                                grid.preview = Some(Box::new(preview));
+                                grid.preview = Some(Arc::new(preview));
                             }
 
                             // println!("{:?}", hit_point);
@@ -280,6 +367,26 @@ impl ModelEditor {
                     }
                 }
             }
+            TheEvent::ValueChanged(id, value) => {
+                if let Some(param) = id.name.strip_prefix("Renderer Param ") {
+                    let value = match value {
+                        TheValue::Float(v) | TheValue::FloatRange(v, _) => Some(*v),
+                        TheValue::Int(v) | TheValue::IntRange(v, _) => Some(*v as F),
+                        _ => None,
+                    };
+                    if let Some(value) = value {
+                        RENDERER.write().unwrap().set_param(param, value);
+                        reset_render();
+                    }
+                } else if id.name.starts_with("render") {
+                    self.apply_settings_value(&id.name, value);
+                    reset_render();
+                } else if id.name == "exportVoxelSize"
+                    && let TheValue::Float(v) | TheValue::FloatRange(v, _) = value
+                {
+                    Arc::make_mut(&mut SCENE.write().unwrap()).voxel_size_mm = v.max(0.001);
+                }
+            }
             TheEvent::RenderViewScrollBy(id, coord) => {
                 if id.name == "ModelView" {
                     // if ui.alt {
@@ -310,6 +417,206 @@ impl ModelEditor {
         redraw
     }
 
+    /// Show the render settings in the settings panel.
+    pub fn set_settings_ui(&self, ui: &mut TheUI, ctx: &mut TheContext) {
+        let settings = *RENDERSETTINGS.read().unwrap();
+        let mut nodeui = TheNodeUI::default();
+
+        let ints = [
+            (
+                "renderSamples",
+                "Samples",
+                "The samples per pixel after which the render stops, 0 never stops.",
+                settings.samples,
+                0..=8192,
+            ),
+            (
+                "renderMinSamples",
+                "Min Samples",
+                "The samples every pixel gets before its noise is estimated.",
+                settings.min_samples,
+                2..=256,
+            ),
+            (
+                "renderMaxBounces",
+                "Max Bounces",
+                "The maximum number of bounces of a path.",
+                settings.max_bounces,
+                0..=64,
+            ),
+            (
+                "renderDiffuseBounces",
+                "Diffuse Bounces",
+                "The maximum number of diffuse bounces.",
+                settings.max_diffuse_bounces,
+                0..=32,
+            ),
+            (
+                "renderGlossyBounces",
+                "Glossy Bounces",
+                "The maximum number of glossy reflections.",
+                settings.max_glossy_bounces,
+                0..=32,
+            ),
+            (
+                "renderTransmissionBounces",
+                "Transmission Bounces",
+                "The maximum number of refractions into and out of transmissive voxels.",
+                settings.max_transmission_bounces,
+                0..=64,
+            ),
+            (
+                "renderVolumeBounces",
+                "Volume Bounces",
+                "The maximum number of scattering events inside volumes.",
+                settings.max_volume_bounces,
+                0..=32,
+            ),
+            (
+                "renderRouletteDepth",
+                "Roulette Depth",
+                "The bounces after which paths are randomly terminated.",
+                settings.roulette_depth,
+                0..=64,
+            ),
+            (
+                "renderFogSteps",
+                "Fog Steps",
+                "The ray marching steps through the fog per path segment.",
+                settings.fog_steps,
+                1..=64,
+            ),
+            (
+                "renderSeed",
+                "Seed",
+                "The seed of the random numbers.",
+                settings.seed,
+                0..=9999,
+            ),
+        ];
+        for (id, name, status, value, range) in ints {
+            nodeui.add_item(TheNodeUIItem::IntEditSlider(
+                id.into(),
+                name.into(),
+                status.into(),
+                value as i32,
+                range,
+                false,
+            ));
+        }
+
+        nodeui.add_item(TheNodeUIItem::FloatEditSlider(
+            "renderNoiseThreshold".into(),
+            "Noise Threshold".into(),
+            "The noise at which pixels stop sampling, 0 disables adaptive sampling.".into(),
+            settings.noise_threshold,
+            0.0..=0.1,
+            false,
+        ));
+
+        nodeui.add_item(TheNodeUIItem::FloatEditSlider(
+            "renderClampIndirect".into(),
+            "Clamp Indirect".into(),
+            "Clamps the indirect light against fireflies, 0 disables it.".into(),
+            settings.clamp_indirect,
+            0.0..=100.0,
+            false,
+        ));
+
+        nodeui.add_item(TheNodeUIItem::FloatEditSlider(
+            "exportVoxelSize".into(),
+            "Voxel Size (mm)".into(),
+            "The size of one voxel in millimetres in exported STL files.".into(),
+            SCENE.read().unwrap().voxel_size_mm,
+            0.01..=100.0,
+            false,
+        ));
+
+        if let Some(layout) = ui.get_text_layout("Node Settings") {
+            nodeui.apply_to_text_layout(layout);
+            ctx.ui.relayout = true;
+        }
+    }
+
+    /// Add the parameters of the active renderer to the tool params.
+    pub fn set_renderer_ui(&self, ui: &mut TheUI, ctx: &mut TheContext) {
+        let renderer = RENDERER.read().unwrap().active();
+
+        if let Some(layout) = ui.get_hlayout("Tool Params") {
+            let mut text = TheText::new(TheId::empty());
+            text.set_text(renderer.name().to_string());
+            layout.add_widget(Box::new(text));
+
+            for param in renderer.params() {
+                match param {
+                    RendererParam::Float(id, name, status, value, range) => {
+                        let mut edit =
+                            TheTextLineEdit::new(TheId::named(&format!("Renderer Param {}", id)));
+                        edit.set_value(TheValue::Float(value));
+                        edit.set_info_text(Some(name));
+                        edit.set_range(TheValue::RangeF32(range));
+                        edit.set_continuous(true);
+                        edit.limiter_mut().set_max_width(140);
+                        edit.set_status_text(&status);
+                        layout.add_widget(Box::new(edit));
+                    }
+                    RendererParam::Int(id, name, status, value, range) => {
+                        let mut edit =
+                            TheTextLineEdit::new(TheId::named(&format!("Renderer Param {}", id)));
+                        edit.set_value(TheValue::Int(value));
+                        edit.set_info_text(Some(name));
+                        edit.set_range(TheValue::RangeI32(range));
+                        edit.set_continuous(true);
+                        edit.limiter_mut().set_max_width(140);
+                        edit.set_status_text(&status);
+                        layout.add_widget(Box::new(edit));
+                    }
+                    RendererParam::Selector(id, _name, status, options, value) => {
+                        let mut drop_down =
+                            TheDropdownMenu::new(TheId::named(&format!("Renderer Param {}", id)));
+                        for option in options {
+                            drop_down.add_option(option);
+                        }
+                        drop_down.set_selected_index(value);
+                        drop_down.set_status_text(&status);
+                        layout.add_widget(Box::new(drop_down));
+                    }
+                }
+            }
+
+            ctx.ui.relayout = true;
+        }
+    }
+
+    /// Apply a value of the render settings UI.
+    fn apply_settings_value(&self, name: &str, value: &TheValue) {
+        let mut settings = RENDERSETTINGS.write().unwrap();
+        match value {
+            TheValue::IntRange(v, _) | TheValue::Int(v) => {
+                let v = (*v).max(0) as u32;
+                match name {
+                    "renderSamples" => settings.samples = v,
+                    "renderMinSamples" => settings.min_samples = v,
+                    "renderMaxBounces" => settings.max_bounces = v,
+                    "renderDiffuseBounces" => settings.max_diffuse_bounces = v,
+                    "renderGlossyBounces" => settings.max_glossy_bounces = v,
+                    "renderTransmissionBounces" => settings.max_transmission_bounces = v,
+                    "renderVolumeBounces" => settings.max_volume_bounces = v,
+                    "renderRouletteDepth" => settings.roulette_depth = v,
+                    "renderFogSteps" => settings.fog_steps = v.max(1),
+                    "renderSeed" => settings.seed = v,
+                    _ => {}
+                }
+            }
+            TheValue::FloatRange(v, _) | TheValue::Float(v) => match name {
+                "renderNoiseThreshold" => settings.noise_threshold = v.max(0.0),
+                "renderClampIndirect" => settings.clamp_indirect = v.max(0.0),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
     /// Get the current time
     pub fn get_time(&self) -> u128 {
         #[cfg(target_arch = "wasm32")]
@@ -332,16 +639,18 @@ impl ModelEditor {
         image_height: usize,
         tile_width: usize,
         tile_height: usize,
-    ) -> Vec<Tile> {
+    ) -> Vec<RenderTile> {
         let mut tiles = Vec::new();
         let mut x = 0;
         let mut y = 0;
         while x < image_width && y < image_height {
-            let tile = Tile {
+            let tile = RenderTile {
                 x,
                 y,
                 width: tile_width,
                 height: tile_height,
+                sample: 0,
+                count: 1,
             };
             tiles.push(tile);
             x += tile_width;
@@ -355,14 +664,6 @@ impl ModelEditor {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Tile {
-    pub x: usize,
-    pub y: usize,
-    pub width: usize,
-    pub height: usize,
-}
-
 /*
 use crate::editor::{NODEEDITOR, RUSTERIX, SCENEMANAGER, SIDEBARMODE, UNDOMANAGER};
 use crate::prelude::*;
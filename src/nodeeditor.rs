@@ -61,6 +61,7 @@ impl NodeEditor {
             NodeContext::Color(index) => {
                 {
                     let mut palette = PALETTE.write().unwrap();
+                    let palette = std::sync::Arc::make_mut(&mut palette);
                     palette.graphs[index as usize] = self.graph.clone();
                     palette.materials[index as usize] = self.graph.evaluate_material();
                 }
@@ -1,16 +1,117 @@
-use theframework::prelude::*;
+use crate::prelude::*;
+use crate::voxel::io::{chunk, read_u32, write_u32};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Project {}
+/// The version of the project format written by this build.
+pub const PROJECT_VERSION: u32 = 2;
 
-impl Default for Project {
-    fn default() -> Self {
-        Self::new()
-    }
+const PROJECT_MAGIC: &[u8; 4] = b"SZPR";
+
+/// A project holds the complete scene: the voxel grid, the palette, the camera, the
+/// scene settings like the lights and the render settings.
+///
+/// On disk a project is a small binary container: the magic `SZPR`, the format version,
+/// the length of the JSON encoded project settings, the JSON itself and finally the voxel
+/// grid in the compact chunk format of `voxel::io::chunk`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Project {
+    pub version: u32,
+
+    #[serde(skip, default = "Project::empty_grid")]
+    pub grid: VoxelGrid,
+    pub palette: Palette,
+    pub camera: CameraState,
+    #[serde(default)]
+    pub scene: Scene,
+    #[serde(default)]
+    pub settings: RenderSettings,
 }
 
 impl Project {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(
+        grid: VoxelGrid,
+        palette: Palette,
+        camera: CameraState,
+        scene: Scene,
+        settings: RenderSettings,
+    ) -> Self {
+        Self {
+            version: PROJECT_VERSION,
+            grid,
+            palette,
+            camera,
+            scene,
+            settings,
+        }
+    }
+
+    /// Save the project to the given path.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Load a project from the given path.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(std::fs::File::open(path)?);
+        Self::read(&mut reader)
+    }
+
+    /// Write the project to the writer.
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let json = serde_json::to_vec(self).map_err(Error::other)?;
+
+        writer.write_all(PROJECT_MAGIC)?;
+        write_u32(writer, PROJECT_VERSION)?;
+        write_u32(writer, json.len() as u32)?;
+        writer.write_all(&json)?;
+        chunk::write_grid(&self.grid, writer)
+    }
+
+    /// Read a project from the reader.
+    pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != PROJECT_MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "not a Shape-Z project file",
+            ));
+        }
+
+        let version = read_u32(reader)?;
+        if version > PROJECT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "project version {} is newer than the supported version {}",
+                    version, PROJECT_VERSION
+                ),
+            ));
+        }
+
+        // The length comes from the file, the buffer only grows with the data actually read
+        let length = read_u32(reader)? as usize;
+        let mut json = vec![];
+        reader.by_ref().take(length as u64).read_to_end(&mut json)?;
+        if json.len() != length {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated project settings",
+            ));
+        }
+
+        let mut project: Project =
+            serde_json::from_slice(&json).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        project.grid = chunk::read_grid(reader)?;
+
+        Ok(project)
+    }
+
+    /// Placeholder until the grid chunk has been read, avoids allocating a default grid.
+    fn empty_grid() -> VoxelGrid {
+        VoxelGrid::empty([0.0; 3], 1)
     }
 }
@@ -1,3 +1,4 @@
+use crate::editor::MODELEDITOR;
 use crate::prelude::*;
 use crate::tools::edit::EditTool;
 
@@ -100,6 +101,9 @@ impl ToolList {
             ctx.ui.redraw_all = true;
         }
 
+        // The parameters of the renderer come first, the tool adds its own
+        MODELEDITOR.read().unwrap().set_renderer_ui(ui, ctx);
+
         self.get_current_tool()
             .tool_event(ToolEvent::Activate, ui, ctx, context);
 
@@ -1,20 +1,357 @@
-use crate::editor::{PALETTE, RENDERBUFFER};
+use crate::editor::{
+    CAMERA, PALETTE, RENDERBUFFER, RENDERER, RENDERSERVICE, RENDERSETTINGS, SCENE, VOXELGRID,
+};
 use crate::prelude::*;
 use std::sync::Arc;
 
+/// Restart the accumulation of the render, the tiles still being rendered are cancelled.
 pub fn reset_render() {
+    RENDERSERVICE.cancel();
     let rb = Arc::clone(&RENDERBUFFER);
     let mut buffer = rb.lock().unwrap();
-    buffer.accum = 1;
+    buffer.reset_accum();
+}
+
+/// Whether frames are denoised: enabled in the render settings and the active renderer
+/// accumulates samples.
+pub fn denoise_enabled() -> bool {
+    RENDERSETTINGS.read().unwrap().denoise && RENDERER.read().unwrap().active().progressive()
+}
+
+/// The frame as shown in the model view and exported, denoised if enabled.
+pub fn final_frame(buffer: &RenderBuffer) -> RenderBuffer {
+    if denoise_enabled() {
+        Denoiser::default().denoise(buffer)
+    } else {
+        buffer.clone()
+    }
+}
+
+/// Toggle the denoiser, the accumulation continues.
+pub fn toggle_denoiser() {
+    let mut settings = RENDERSETTINGS.write().unwrap();
+    settings.denoise = !settings.denoise;
+}
+
+/// Switch the renderer of the model view by name and restart the render.
+pub fn set_renderer(name: &str) {
+    if RENDERER.write().unwrap().set_active(name) {
+        reset_render();
+    }
 }
 
 pub fn update_palette_ui(ui: &mut TheUI, ctx: &mut TheContext) {
     if let Some(picker) = ui.get_palette_picker("PalettePicker") {
         let mut palette = ThePalette::default();
-        let mats = PALETTE.write().unwrap();
+        let mats = PALETTE.read().unwrap();
         for (index, mat) in mats.materials.iter().enumerate() {
             palette.colors[index] = Some(TheColor::from(mat.base_color));
         }
         picker.set_palette(palette);
     }
 }
+
+/// Collect the current scene (grid, palette, camera, lights and render settings) into a
+/// project.
+pub fn scene_to_project() -> Project {
+    Project::new(
+        VoxelGrid::clone(&VOXELGRID.read().unwrap()),
+        Palette::clone(&PALETTE.read().unwrap()),
+        CAMERA.read().unwrap().state(),
+        Scene::clone(&SCENE.read().unwrap()),
+        *RENDERSETTINGS.read().unwrap(),
+    )
+}
+
+/// Replace the current scene with the content of the project. The scene is replaced even if
+/// the environment map can not be loaded, the error is returned to be reported.
+pub fn project_to_scene(project: Project) -> std::io::Result<()> {
+    *VOXELGRID.write().unwrap() = Arc::new(project.grid);
+    *PALETTE.write().unwrap() = Arc::new(project.palette);
+    *CAMERA.write().unwrap() = project.camera.to_camera();
+    *RENDERSETTINGS.write().unwrap() = project.settings;
+
+    // Projects only reference the environment map, load the image again. If the file is gone
+    // the settings are kept and the background stays black.
+    let mut scene = project.scene;
+    let mut result = Ok(());
+    if let Background::Environment(environment) = &mut scene.background {
+        result = environment.reload();
+    }
+    *SCENE.write().unwrap() = Arc::new(scene);
+    reset_render();
+    result
+}
+
+/// Load an equirectangular `.hdr` map as the environment of the scene.
+pub fn load_environment(path: &std::path::Path) -> std::io::Result<()> {
+    let environment = Environment::load(path)?;
+    Arc::make_mut(&mut SCENE.write().unwrap()).background = Background::Environment(environment);
+    reset_render();
+    Ok(())
+}
+
+/// Set the background of the scene. The procedural sky brings its own sun, it replaces the
+/// directional lights of the scene.
+pub fn set_background(background: Background) {
+    {
+        let mut scene = SCENE.write().unwrap();
+        let scene = Arc::make_mut(&mut scene);
+        if matches!(background, Background::Sky(_)) {
+            scene
+                .lights
+                .retain(|light| !matches!(light.light_type, LightType::Directional { .. }));
+        }
+        scene.background = background;
+    }
+    reset_render();
+}
+
+/// Rotate the environment map or the sun of the sky around the up axis by the angle in
+/// radians.
+pub fn rotate_environment(angle: F) {
+    match &mut Arc::make_mut(&mut SCENE.write().unwrap()).background {
+        Background::Environment(environment) => {
+            environment.yaw = (environment.yaw + angle).rem_euclid(crate::F_TAU);
+        }
+        Background::Sky(sky) => {
+            sky.azimuth = (sky.azimuth + angle).rem_euclid(crate::F_TAU);
+        }
+        Background::Color(_) => {}
+    }
+    reset_render();
+}
+
+/// Change the elevation of the sun of the sky by the angle in radians.
+pub fn raise_sun(angle: F) {
+    if let Background::Sky(sky) = &mut Arc::make_mut(&mut SCENE.write().unwrap()).background {
+        sky.elevation = (sky.elevation + angle).clamp(-0.2, crate::F_PI * 0.5);
+    }
+    reset_render();
+}
+
+/// Switch the fog of the scene on (with the default settings) or off.
+pub fn toggle_fog() {
+    {
+        let mut scene = SCENE.write().unwrap();
+        let scene = Arc::make_mut(&mut scene);
+        scene.fog = match scene.fog {
+            Some(_) => None,
+            None => Some(Fog::default()),
+        };
+    }
+    reset_render();
+}
+
+/// Scale the density of the fog and the atmosphere.
+pub fn scale_fog(factor: F) {
+    if let Some(fog) = &mut Arc::make_mut(&mut SCENE.write().unwrap()).fog {
+        fog.density *= factor;
+        fog.atmosphere *= factor;
+    }
+    reset_render();
+}
+
+/// Export the beauty pass to the path as OpenEXR and every AOV pass next to it, named after
+/// the pass (`render.exr`, `render_depth.exr`, ...).
+pub fn export_passes(path: &std::path::Path) -> std::io::Result<()> {
+    let buffer = RENDERBUFFER.lock().unwrap();
+    final_frame(&buffer).save_exr(path)?;
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    for aov in Aov::ALL {
+        let name = format!(
+            "{}_{}.exr",
+            stem,
+            aov.name().to_lowercase().replace(' ', "_")
+        );
+        buffer.aov(aov).save_exr(&path.with_file_name(name))?;
+    }
+
+    Ok(())
+}
+
+/// Export the visible surface of the grid as a polygon mesh, the format is chosen by the
+/// extension of the path (obj, ply or glb). For OBJ the material library is written next to it.
+pub fn export_mesh(path: &std::path::Path, voxel_units: bool) -> std::io::Result<()> {
+    use crate::voxel::io::{gltf, mesh::Mesh, obj, ply};
+    use std::io::{BufWriter, Error, ErrorKind, Write};
+
+    let mesh = Mesh::from_grid(&VOXELGRID.read().unwrap(), voxel_units);
+    let palette = PALETTE.read().unwrap();
+    if mesh.quads.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the grid has no voxels",
+        ));
+    }
+
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "obj" => {
+            let mtl_path = path.with_extension("mtl");
+            let mtl_name = mtl_path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let mut writer = BufWriter::new(std::fs::File::create(path)?);
+            let mut mtl_writer = BufWriter::new(std::fs::File::create(&mtl_path)?);
+            obj::export_obj(&mut writer, &mut mtl_writer, &mtl_name, &mesh, &palette)?;
+            mtl_writer.flush()?;
+            writer.flush()
+        }
+        "ply" => {
+            let mut writer = BufWriter::new(std::fs::File::create(path)?);
+            ply::export_ply(&mut writer, &mesh, &palette)?;
+            writer.flush()
+        }
+        "glb" => {
+            let mut writer = BufWriter::new(std::fs::File::create(path)?);
+            gltf::export_glb(&mut writer, &mesh, &palette)?;
+            writer.flush()
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unsupported mesh format '{}'", extension),
+        )),
+    }
+}
+
+/// Export the grid as a watertight STL for 3D printing, scaled by the voxel size of the scene.
+pub fn export_stl(path: &std::path::Path, ascii: bool) -> std::io::Result<()> {
+    use crate::voxel::io::stl;
+
+    // Only create the file once the export succeeded
+    let mut data = vec![];
+    stl::export_stl(
+        &mut data,
+        &VOXELGRID.read().unwrap(),
+        SCENE.read().unwrap().voxel_size_mm,
+        ascii,
+    )?;
+    std::fs::write(path, data)
+}
+
+/// Import a Qubicle file into the grid and palette, the format is chosen by the extension of
+/// the path (qb or qbt).
+pub fn import_qubicle(path: &std::path::Path) -> std::io::Result<()> {
+    use crate::voxel::io::qubicle;
+
+    let data = std::fs::read(path)?;
+    let mut grid = VOXELGRID.write().unwrap();
+    let mut palette = PALETTE.write().unwrap();
+    let (grid, palette) = (Arc::make_mut(&mut grid), Arc::make_mut(&mut palette));
+
+    if has_extension(path, "qbt") {
+        qubicle::import_qbt(&data, grid, palette)
+    } else {
+        qubicle::import_qb(&data, grid, palette)
+    }
+}
+
+/// Export the grid as a Qubicle file, the format is chosen by the extension of the path
+/// (qb or qbt).
+pub fn export_qubicle(path: &std::path::Path) -> std::io::Result<()> {
+    use crate::voxel::io::qubicle;
+    use std::io::{BufWriter, Write};
+
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+    let grid = VOXELGRID.read().unwrap();
+    let palette = PALETTE.read().unwrap();
+
+    if has_extension(path, "qbt") {
+        qubicle::export_qbt(&mut writer, &grid, &palette)?;
+    } else {
+        qubicle::export_qb(&mut writer, &grid, &palette)?;
+    }
+    writer.flush()
+}
+
+/// Export the grid as a MagicaVoxel `.vox` file.
+pub fn export_vox(path: &std::path::Path) -> std::io::Result<()> {
+    use crate::voxel::io::vox;
+
+    // Only create the file once the export succeeded
+    let mut data = vec![];
+    vox::export_vox(
+        &mut data,
+        &VOXELGRID.read().unwrap(),
+        &PALETTE.read().unwrap(),
+    )?;
+    std::fs::write(path, data)
+}
+
+/// Case insensitive check of the file extension.
+fn has_extension(path: &std::path::Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case(extension))
+}
+
+/// Import a greyscale heightmap PNG as terrain, one tile high, with the default height bands.
+pub fn import_heightmap(path: &std::path::Path) -> std::io::Result<()> {
+    use crate::voxel::io::pngimport;
+
+    let data = std::fs::read(path)?;
+    let mut grid = VOXELGRID.write().unwrap();
+    let grid = Arc::make_mut(&mut grid);
+    let bands = pngimport::default_bands(&PALETTE.read().unwrap());
+    let max_height = grid.density;
+
+    pngimport::import_heightmap(&data, grid, max_height, &bands)
+}
+
+/// Import the slice stack the given PNG belongs to: all PNG files in its folder with the same
+/// name followed by a number, e.g. `slice_1.png`, `slice_2.png`, ... `slice_10.png`. The
+/// slices are stacked by their number.
+pub fn import_slices(path: &std::path::Path) -> std::io::Result<()> {
+    use crate::voxel::io::pngimport;
+
+    let stem = |p: &std::path::Path| {
+        p.file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let picked = stem(path);
+    let prefix = picked.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    // The number after the prefix, None for files of other stacks
+    let slice_number = |p: &std::path::Path| -> Option<u128> {
+        let number = stem(p).strip_prefix(prefix)?.to_string();
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        number.parse().ok()
+    };
+
+    let folder = path.parent().unwrap_or(path);
+    let mut files: Vec<(u128, std::path::PathBuf)> = std::fs::read_dir(folder)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| has_extension(p, "png"))
+        .filter_map(|p| slice_number(&p).map(|number| (number, p)))
+        .collect();
+    files.sort();
+
+    // A single image without a number
+    if files.is_empty() {
+        files.push((0, path.to_path_buf()));
+    }
+
+    let slices = files
+        .iter()
+        .map(|(_, p)| std::fs::read(p))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    pngimport::import_slices(
+        &slices,
+        Arc::make_mut(&mut VOXELGRID.write().unwrap()),
+        &PALETTE.read().unwrap(),
+    )
+}
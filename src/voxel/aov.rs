@@ -0,0 +1,130 @@
+use crate::prelude::*;
+use vek::Vec3;
+
+/// An arbitrary output variable, a pass of the first hit rendered next to the beauty pass.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+    /// The distance from the camera to the first hit, infinite for misses.
+    Depth,
+    /// The world space normal of the hit face, facing the camera.
+    Normal,
+    /// The linear base color of the hit voxel.
+    Albedo,
+    /// The palette index of the hit voxel, -1 for misses.
+    MaterialId,
+    /// The world space position of the hit.
+    Position,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 5] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::MaterialId,
+        Aov::Position,
+    ];
+
+    /// The number of floats of all passes of a pixel.
+    pub const CHANNELS: usize = 11;
+
+    pub fn name(&self) -> &str {
+        match self {
+            Aov::Depth => "Depth",
+            Aov::Normal => "Normal",
+            Aov::Albedo => "Albedo",
+            Aov::MaterialId => "Material ID",
+            Aov::Position => "Position",
+        }
+    }
+
+    /// The number of floats of the pass.
+    pub fn channels(&self) -> usize {
+        match self {
+            Aov::Depth | Aov::MaterialId => 1,
+            Aov::Normal | Aov::Albedo | Aov::Position => 3,
+        }
+    }
+
+    /// The offset of the pass in the floats of a pixel.
+    pub fn offset(&self) -> usize {
+        match self {
+            Aov::Depth => 0,
+            Aov::Normal => 1,
+            Aov::Albedo => 4,
+            Aov::MaterialId => 7,
+            Aov::Position => 8,
+        }
+    }
+
+    /// Whether the hit samples of the pass are averaged, the palette index of the first hit
+    /// is kept as a mean of indices has no meaning.
+    pub fn accumulates(&self) -> bool {
+        *self != Aov::MaterialId
+    }
+}
+
+/// The values of all passes for one sample of a pixel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AovSample {
+    pub depth: F,
+    pub normal: Vec3<F>,
+    pub albedo: Vec3<F>,
+    pub material: Option<u8>,
+    pub position: Vec3<F>,
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self {
+            depth: F::INFINITY,
+            normal: Vec3::zero(),
+            albedo: Vec3::zero(),
+            material: None,
+            position: Vec3::zero(),
+        }
+    }
+}
+
+impl AovSample {
+    /// The passes of the first hit of a camera ray. Misses have infinite depth, no material
+    /// and are zero otherwise.
+    pub fn from_hit(ray: &Ray, hit: &HitRecord, palette: &Palette) -> Self {
+        let HitType::Voxel(m) = hit.hit else {
+            return Self::default();
+        };
+
+        // The DDA normal is the step direction, flip it towards the camera
+        let normal = if hit.normal.dot(ray.dir) > 0.0 {
+            -hit.normal
+        } else {
+            hit.normal
+        };
+
+        Self {
+            // The distance of the tile DDA is relative to the tile
+            depth: (hit.hitpoint - ray.origin).magnitude(),
+            normal,
+            albedo: palette.get(m).base_color_linear(),
+            material: Some(m),
+            position: hit.hitpoint,
+        }
+    }
+
+    /// The floats of all passes, in the layout of `Aov::offset`.
+    pub fn to_array(&self) -> [F; Aov::CHANNELS] {
+        [
+            self.depth,
+            self.normal.x,
+            self.normal.y,
+            self.normal.z,
+            self.albedo.x,
+            self.albedo.y,
+            self.albedo.z,
+            self.material.map(|m| m as F).unwrap_or(-1.0),
+            self.position.x,
+            self.position.y,
+            self.position.z,
+        ]
+    }
+}
@@ -0,0 +1,73 @@
+use crate::prelude::*;
+use crate::voxel::color::srgb_to_linear;
+use vek::{Vec2, Vec3};
+
+/// Provides the radiance for rays leaving the grid. Providers which are bright enough to
+/// light the scene can be importance sampled and may come with a sun.
+pub trait BackgroundProvider {
+    /// The linear radiance arriving from the given direction.
+    fn radiance(&self, dir: Vec3<F>) -> Vec3<F>;
+
+    /// Sample a direction for next event estimation, None if the provider is not sampled.
+    fn sample(&self, _rnd: Vec2<F>) -> Option<LightSample> {
+        None
+    }
+
+    /// The solid angle pdf of `sample` for the given direction.
+    fn pdf(&self, _dir: Vec3<F>) -> F {
+        0.0
+    }
+
+    /// The sun light belonging to the background, if any.
+    fn sun(&self) -> Option<Light> {
+        None
+    }
+}
+
+/// The background of the scene.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Background {
+    /// A constant sRGB color.
+    Color(Vec3<F>),
+    /// An equirectangular HDR map.
+    Environment(Environment),
+    /// The procedural sun and sky.
+    Sky(Sky),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color(Vec3::broadcast(0.2))
+    }
+}
+
+impl BackgroundProvider for Background {
+    fn radiance(&self, dir: Vec3<F>) -> Vec3<F> {
+        match self {
+            Background::Color(color) => srgb_to_linear(*color),
+            Background::Environment(environment) => environment.radiance(dir),
+            Background::Sky(sky) => sky.radiance(dir),
+        }
+    }
+
+    fn sample(&self, rnd: Vec2<F>) -> Option<LightSample> {
+        match self {
+            Background::Environment(environment) => environment.sample(rnd),
+            _ => None,
+        }
+    }
+
+    fn pdf(&self, dir: Vec3<F>) -> F {
+        match self {
+            Background::Environment(environment) => environment.pdf(dir),
+            _ => 0.0,
+        }
+    }
+
+    fn sun(&self) -> Option<Light> {
+        match self {
+            Background::Sky(sky) => sky.sun(),
+            _ => None,
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::prelude::*;
 use vek::{Vec2, Vec3};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Iso {
     pub center: Vec3<f32>,
 
@@ -32,6 +32,10 @@ impl Camera for Iso {
         "Iso"
     }
 
+    fn state(&self) -> CameraState {
+        CameraState::Iso(self.clone())
+    }
+
     /// Zoom the camera in or out based on vertical mouse delta
     fn zoom(&mut self, delta: f32) {
         let zoom_sensitivity = 0.05;
@@ -14,6 +14,9 @@ pub trait Camera: Send + Sync {
     /// Returns the name of the camera.
     fn name(&self) -> &str;
 
+    /// Returns the serializable state of the camera.
+    fn state(&self) -> CameraState;
+
     /// Set the origin of the camera.
     fn set_origin(&mut self, origin: Vec3<F>) {}
 
@@ -32,3 +35,28 @@ pub trait Camera: Send + Sync {
     /// Create a ray.
     fn create_ray(&self, uv: Vec2<F>, screen_size: Vec2<F>, offset: Vec2<F>) -> Ray;
 }
+
+/// The serializable state of one of the cameras, used to store the camera in the project.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum CameraState {
+    Orbit(Orbit),
+    Iso(Iso),
+    Pinhole(Pinhole),
+}
+
+impl Default for CameraState {
+    fn default() -> Self {
+        CameraState::Orbit(Orbit::new())
+    }
+}
+
+impl CameraState {
+    /// Create the camera described by this state.
+    pub fn to_camera(&self) -> Box<dyn Camera> {
+        match self {
+            CameraState::Orbit(camera) => Box::new(camera.clone()),
+            CameraState::Iso(camera) => Box::new(camera.clone()),
+            CameraState::Pinhole(camera) => Box::new(camera.clone()),
+        }
+    }
+}
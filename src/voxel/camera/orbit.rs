@@ -1,6 +1,7 @@
 use crate::prelude::*;
 use vek::{Vec2, Vec3};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Orbit {
     pub center: Vec3<F>,
     pub distance: F,
@@ -35,6 +36,10 @@ impl Camera for Orbit {
         "Orbit"
     }
 
+    fn state(&self) -> CameraState {
+        CameraState::Orbit(self.clone())
+    }
+
     fn set_center(&mut self, center: Vec3<F>) {
         self.center = center;
     }
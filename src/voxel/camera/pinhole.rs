@@ -1,6 +1,7 @@
 use crate::prelude::*;
 use vek::{Vec2, Vec3};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Pinhole {
     pub origin: Vec3<F>,
     pub center: Vec3<F>,
@@ -23,6 +24,10 @@ impl Camera for Pinhole {
         "Pinhole"
     }
 
+    fn state(&self) -> CameraState {
+        CameraState::Pinhole(self.clone())
+    }
+
     fn set_origin(&mut self, origin: Vec3<F>) {
         self.origin = origin;
     }
@@ -0,0 +1,32 @@
+//! Conversions between sRGB and linear colors, shared by the palette, the renderers and the
+//! importers.
+
+use crate::F;
+use vek::Vec3;
+
+/// Convert an sRGB color to linear.
+pub fn srgb_to_linear(c: Vec3<F>) -> Vec3<F> {
+    c.map(|c| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+/// Convert a linear color to sRGB.
+pub fn linear_to_srgb(c: Vec3<F>) -> Vec3<F> {
+    c.map(|c| {
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
+/// The Rec. 709 luminance of a linear color.
+pub fn luminance(c: Vec3<F>) -> F {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
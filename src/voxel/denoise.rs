@@ -0,0 +1,282 @@
+use crate::prelude::*;
+use crate::voxel::color::luminance;
+use rayon::prelude::*;
+use vek::Vec3;
+
+/// The 1D weights of the B3 spline kernel of the wavelet transform.
+const KERNEL: [F; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// The smallest albedo the light is divided by, keeps dark voxels from blowing up.
+const MIN_ALBEDO: F = 0.01;
+
+/// An edge-avoiding à-trous wavelet filter for accumulated path traced frames (Dammertz et al.
+/// 2010, with the variance guided luminance weight of SVGF).
+///
+/// The light arriving at the first hit is filtered instead of the color, so voxel colors stay
+/// sharp, and the AOVs of the buffer stop the filter at faces of a different orientation and
+/// at depth discontinuities. The luminance weight follows the standard error of each pixel,
+/// the filter fades out as the accumulation converges.
+#[derive(Clone, Copy, Debug)]
+pub struct Denoiser {
+    /// The number of passes, the footprint of the kernel doubles with every pass.
+    pub iterations: u32,
+    /// The luminance difference the filter tolerates, in standard errors of the pixel.
+    pub sigma_luminance: F,
+    /// The exponent of the cosine between two normals.
+    pub sigma_normal: F,
+    /// The depth difference the filter tolerates, relative to the depth gradient.
+    pub sigma_depth: F,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 1.0,
+        }
+    }
+}
+
+/// The guides of a pixel, read once from the AOVs.
+#[derive(Clone, Copy)]
+struct Guide {
+    hit: bool,
+    normal: Vec3<F>,
+    albedo: Vec3<F>,
+    depth: F,
+    /// The depth change to the next pixel along x and y.
+    gradient: (F, F),
+}
+
+impl Denoiser {
+    /// Return the denoised copy of the buffer. Pixels without a hit keep their color.
+    pub fn denoise(&self, buffer: &RenderBuffer) -> RenderBuffer {
+        let (width, height) = (buffer.width, buffer.height);
+        let guides = Self::guides(buffer);
+
+        // Divide the albedo out, the light varies slowly where the colors change
+        let mut light: Vec<Vec3<F>> = (0..width * height)
+            .map(|i| {
+                let c = &buffer.pixels[i * 4..i * 4 + 3];
+                let c = Vec3::new(c[0], c[1], c[2]);
+                if guides[i].hit {
+                    c / guides[i].albedo
+                } else {
+                    c
+                }
+            })
+            .collect();
+
+        // The variance of the light, pre-filtered as the estimate of a pixel is noisy itself
+        let variance: Vec<F> = (0..width * height)
+            .map(|i| {
+                let v = buffer.variance(i % width, i / width);
+                let a = luminance(guides[i].albedo);
+                if v.is_finite() { v / (a * a) } else { F::MAX }
+            })
+            .collect();
+        let mut variance = Self::blur_variance(&variance, &guides, width, height);
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let luminances: Vec<F> = light.iter().map(|l| luminance(*l)).collect();
+            let mut next_light = light.clone();
+            let mut next_variance = variance.clone();
+
+            next_light
+                .par_chunks_mut(width)
+                .zip(next_variance.par_chunks_mut(width))
+                .enumerate()
+                .for_each(|(y, (light_row, variance_row))| {
+                    for x in 0..width {
+                        if let Some((l, v)) = self.filter(
+                            x,
+                            y,
+                            step,
+                            &light,
+                            &luminances,
+                            &variance,
+                            &guides,
+                            width,
+                            height,
+                        ) {
+                            light_row[x] = l;
+                            variance_row[x] = v;
+                        }
+                    }
+                });
+
+            light = next_light;
+            variance = next_variance;
+        }
+
+        let mut out = buffer.clone();
+        for (i, guide) in guides.iter().enumerate() {
+            if guide.hit {
+                let c = light[i] * guide.albedo;
+                out.pixels[i * 4..i * 4 + 3].copy_from_slice(&[c.x, c.y, c.z]);
+            }
+        }
+        out
+    }
+
+    /// One filter pass at a pixel, returns the light and its variance. None for misses.
+    #[allow(clippy::too_many_arguments)]
+    fn filter(
+        &self,
+        x: usize,
+        y: usize,
+        step: i32,
+        light: &[Vec3<F>],
+        luminances: &[F],
+        variance: &[F],
+        guides: &[Guide],
+        width: usize,
+        height: usize,
+    ) -> Option<(Vec3<F>, F)> {
+        let p = y * width + x;
+        let guide = &guides[p];
+        if !guide.hit {
+            return None;
+        }
+
+        let lum = luminances[p];
+        let lum_tolerance = self.sigma_luminance * variance[p].sqrt() + 1e-4;
+
+        let mut sum = Vec3::zero();
+        let mut sum_variance = 0.0;
+        let mut sum_weight = 0.0;
+
+        for (ky, hy) in KERNEL.iter().enumerate() {
+            let dy = (ky as i32 - 2) * step;
+            let qy = y as i32 + dy;
+            if qy < 0 || qy >= height as i32 {
+                continue;
+            }
+            for (kx, hx) in KERNEL.iter().enumerate() {
+                let dx = (kx as i32 - 2) * step;
+                let qx = x as i32 + dx;
+                if qx < 0 || qx >= width as i32 {
+                    continue;
+                }
+
+                let q = qy as usize * width + qx as usize;
+                let other = &guides[q];
+                if !other.hit {
+                    continue;
+                }
+
+                let mut weight = hx * hy;
+                if q != p {
+                    // Voxel faces mostly share the exact normal, skip the power for those
+                    let cos = guide.normal.dot(other.normal);
+                    if cos <= 0.0 {
+                        continue;
+                    } else if cos < 0.9999 {
+                        weight *= cos.powf(self.sigma_normal);
+                    }
+
+                    let expected =
+                        guide.gradient.0 * dx.abs() as F + guide.gradient.1 * dy.abs() as F;
+                    let depth = (guide.depth - other.depth).abs()
+                        / (self.sigma_depth * expected + 1e-3 * guide.depth);
+                    let lum = (lum - luminances[q]).abs() / lum_tolerance;
+
+                    weight *= (-(depth + lum)).exp();
+                }
+
+                sum += light[q] * weight;
+                sum_variance += variance[q].min(F::MAX / 64.0) * weight * weight;
+                sum_weight += weight;
+            }
+        }
+
+        Some((sum / sum_weight, sum_variance / (sum_weight * sum_weight)))
+    }
+
+    /// Read the guides of all pixels from the AOVs of the buffer.
+    fn guides(buffer: &RenderBuffer) -> Vec<Guide> {
+        let (width, height) = (buffer.width, buffer.height);
+        let mut guides: Vec<Guide> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                Guide {
+                    hit: buffer.is_hit(x, y),
+                    normal: Vec3::<F>::from(buffer.aov_at(x, y, Aov::Normal))
+                        .try_normalized()
+                        .unwrap_or_default(),
+                    albedo: Vec3::<F>::from(buffer.aov_at(x, y, Aov::Albedo))
+                        .map(|v| v.max(MIN_ALBEDO)),
+                    depth: buffer.aov_at(x, y, Aov::Depth)[0],
+                    gradient: (0.0, 0.0),
+                }
+            })
+            .collect();
+
+        // The smaller one-sided difference, so edges do not widen the tolerance
+        let difference = |guides: &[Guide], p: usize, a: Option<usize>, b: Option<usize>| {
+            [a, b]
+                .into_iter()
+                .flatten()
+                .filter(|q| guides[*q].hit)
+                .map(|q| (guides[q].depth - guides[p].depth).abs())
+                .fold(F::MAX, F::min)
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                if !guides[p].hit {
+                    continue;
+                }
+                let dx = difference(
+                    &guides,
+                    p,
+                    x.checked_sub(1).map(|x| y * width + x),
+                    (x + 1 < width).then_some(p + 1),
+                );
+                let dy = difference(
+                    &guides,
+                    p,
+                    y.checked_sub(1).map(|y| y * width + x),
+                    (y + 1 < height).then_some(p + width),
+                );
+                let gradient = |d: F| if d == F::MAX { 0.0 } else { d };
+                guides[p].gradient = (gradient(dx), gradient(dy));
+            }
+        }
+
+        guides
+    }
+
+    /// A 3x3 binomial blur of the variance over the hits.
+    fn blur_variance(variance: &[F], guides: &[Guide], width: usize, height: usize) -> Vec<F> {
+        let mut out = variance.to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                if !guides[y * width + x].hit {
+                    continue;
+                }
+                let mut sum = 0.0;
+                let mut sum_weight = 0.0;
+                for dy in -1..=1_i32 {
+                    for dx in -1..=1_i32 {
+                        let (qx, qy) = (x as i32 + dx, y as i32 + dy);
+                        if qx < 0 || qy < 0 || qx >= width as i32 || qy >= height as i32 {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        if guides[q].hit {
+                            let weight = KERNEL[(dx + 2) as usize] * KERNEL[(dy + 2) as usize];
+                            sum += variance[q].min(F::MAX / 64.0) * weight;
+                            sum_weight += weight;
+                        }
+                    }
+                }
+                out[y * width + x] = sum / sum_weight;
+            }
+        }
+        out
+    }
+}
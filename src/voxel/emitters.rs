@@ -0,0 +1,206 @@
+use crate::prelude::*;
+use crate::voxel::color::luminance;
+use rayon::prelude::*;
+use theframework::prelude::FxHashMap;
+use vek::Vec3;
+
+/// A voxel with an emissive material.
+#[derive(Clone, Debug)]
+pub struct EmissiveVoxel {
+    /// The global voxel coordinate.
+    pub voxel: Vec3<i32>,
+    /// The emitted radiance, emission color times strength.
+    pub radiance: Vec3<F>,
+    /// The faces light can leave through, bit `2 * axis + positive`.
+    pub faces: u8,
+}
+
+/// The emissive voxels of the grid, sampled as light sources proportional to their power.
+#[derive(Clone, Debug, Default)]
+pub struct Emitters {
+    pub voxels: Vec<EmissiveVoxel>,
+    /// The cumulative power of the voxels, normalized to 1.
+    cdf: Vec<F>,
+    /// Global voxel coordinate to the index in `voxels`.
+    lookup: FxHashMap<Coord, usize>,
+    density: F,
+}
+
+impl Emitters {
+    /// Collect the emissive voxels of the grid which have an exposed face, a face next to
+    /// an empty or transmissive voxel. Enclosed emitters can never be seen by a light sample.
+    /// The grid is only scanned if the palette contains emissive materials.
+    pub fn build(grid: &VoxelGrid, palette: &Palette) -> Self {
+        let mut emitters = Self {
+            density: grid.density_f,
+            ..Default::default()
+        };
+
+        let radiance: Vec<Vec3<F>> = palette
+            .materials
+            .iter()
+            .map(|m| m.emission_color * m.emission_strength)
+            .collect();
+        if radiance.iter().all(|r| luminance(*r) <= 0.0) {
+            return emitters;
+        }
+
+        let d = grid.density as i32;
+        let radiance = &radiance;
+        let passes = &|v: Vec3<i32>| {
+            grid.get_voxel(v)
+                .is_none_or(|m| palette.get(m).transmission > 0.0)
+        };
+        let voxels: Vec<EmissiveVoxel> = grid
+            .tiles
+            .par_iter()
+            .filter(|(_, tile)| tile.has_voxels)
+            .flat_map_iter(|(key, tile)| {
+                let origin = Vec3::new(key.0, key.1, key.2) * d;
+                tile.voxels
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(index, voxel)| {
+                        let radiance = radiance[(*voxel)? as usize];
+                        if luminance(radiance) <= 0.0 {
+                            return None;
+                        }
+                        let index = index as i32;
+                        let voxel = origin + Vec3::new(index % d, (index / d) % d, index / (d * d));
+
+                        let mut faces = 0;
+                        for axis in 0..3 {
+                            for positive in [false, true] {
+                                let mut neighbour = voxel;
+                                neighbour[axis] += if positive { 1 } else { -1 };
+                                if passes(neighbour) {
+                                    faces |= 1 << (2 * axis + positive as usize);
+                                }
+                            }
+                        }
+                        (faces != 0).then_some(EmissiveVoxel {
+                            voxel,
+                            radiance,
+                            faces,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut total = 0.0;
+        for emitter in voxels {
+            total += luminance(emitter.radiance);
+            let voxel = emitter.voxel;
+            emitters
+                .lookup
+                .insert((voxel.x, voxel.y, voxel.z), emitters.voxels.len());
+            emitters.voxels.push(emitter);
+            emitters.cdf.push(total);
+        }
+
+        for c in &mut emitters.cdf {
+            *c /= total;
+        }
+
+        emitters
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    /// Sample a point on an emissive voxel as seen from `p`. The voxel is picked with `rnd.x`
+    /// proportional to its power, then a point on one of its faces towards `p`.
+    pub fn sample(&self, p: Vec3<F>, rnd: Vec3<F>) -> Option<LightSample> {
+        let index = self
+            .cdf
+            .partition_point(|c| *c <= rnd.x)
+            .min(self.cdf.len().checked_sub(1)?);
+        let emitter = &self.voxels[index];
+
+        // Reuse the remainder of rnd.x to pick the face
+        let low = if index > 0 { self.cdf[index - 1] } else { 0.0 };
+        let select = self.cdf[index] - low;
+        let u = ((rnd.x - low) / select).clamp(0.0, 0.9999);
+
+        let faces = self.faces_towards(emitter, p);
+        if faces.is_empty() {
+            return None;
+        }
+        let (axis, positive) = faces[(u * faces.len() as F) as usize];
+
+        let size = 1.0 / self.density;
+        let mut point = emitter.voxel.map(|v| v as F) * size;
+        point[axis] += if positive { size } else { 0.0 };
+        point[(axis + 1) % 3] += rnd.y * size;
+        point[(axis + 2) % 3] += rnd.z * size;
+
+        let to_light = point - p;
+        let distance_sq = to_light.magnitude_squared();
+        let distance = distance_sq.sqrt();
+        let direction = to_light / distance;
+        let cos_light = direction[axis].abs();
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        let area = size * size * faces.len() as F;
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: emitter.radiance,
+            pdf: select * distance_sq / (cos_light * area),
+        })
+    }
+
+    /// The solid angle pdf with which `sample` picks `point` on the voxel, seen from `p`.
+    pub fn pdf(&self, voxel: Vec3<i32>, p: Vec3<F>, point: Vec3<F>) -> F {
+        let Some(index) = self.lookup.get(&(voxel.x, voxel.y, voxel.z)) else {
+            return 0.0;
+        };
+        let select = self.cdf[*index] - if *index > 0 { self.cdf[index - 1] } else { 0.0 };
+
+        let faces = self.faces_towards(&self.voxels[*index], p);
+        if faces.is_empty() {
+            return 0.0;
+        }
+
+        // The face the point lies on is the one with the largest offset from the centre
+        let size = 1.0 / self.density;
+        let offset = point - (voxel.map(|v| v as F) + 0.5) * size;
+        let axis = (0..3)
+            .max_by(|a, b| offset[*a].abs().total_cmp(&offset[*b].abs()))
+            .unwrap_or(0);
+
+        let to_light = point - p;
+        let distance_sq = to_light.magnitude_squared();
+        let cos_light = (to_light[axis] / distance_sq.sqrt()).abs();
+        if cos_light <= 0.0 {
+            return 0.0;
+        }
+
+        let area = size * size * faces.len() as F;
+        select * distance_sq / (cos_light * area)
+    }
+
+    /// The exposed faces of the emitter (axis, positive) which face the point.
+    fn faces_towards(&self, emitter: &EmissiveVoxel, p: Vec3<F>) -> Vec<(usize, bool)> {
+        let p = p * self.density;
+        let voxel = emitter.voxel;
+        let mut faces = Vec::with_capacity(3);
+        for axis in 0..3 {
+            let face = if p[axis] > (voxel[axis] + 1) as F {
+                (axis, true)
+            } else if p[axis] < voxel[axis] as F {
+                (axis, false)
+            } else {
+                continue;
+            };
+            if emitter.faces & (1 << (2 * face.0 + face.1 as usize)) != 0 {
+                faces.push(face);
+            }
+        }
+        faces
+    }
+}
@@ -0,0 +1,210 @@
+use crate::prelude::*;
+use crate::voxel::color::luminance;
+use crate::voxel::io::hdr;
+use crate::{F_PI, F_TAU};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use vek::{Vec2, Vec3};
+
+/// An equirectangular HDR environment map, used as the background and as a light.
+///
+/// Only the path, rotation and intensity are stored in the project, the image is loaded
+/// from the path. The image and its distributions are shared between clones.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Environment {
+    /// The `.hdr` file the map was loaded from.
+    pub path: PathBuf,
+    /// Rotation around the up axis in radians.
+    pub yaw: F,
+    /// Multiplier of the map radiance.
+    pub intensity: F,
+
+    #[serde(skip)]
+    width: usize,
+    #[serde(skip)]
+    height: usize,
+    /// Linear radiance, top row first.
+    #[serde(skip)]
+    pixels: Arc<[Vec3<F>]>,
+    /// Normalized CDF over the rows.
+    #[serde(skip)]
+    marginal: Arc<[F]>,
+    /// Normalized CDF over the pixels of every row.
+    #[serde(skip)]
+    conditional: Arc<[F]>,
+    /// The integral of the sampling function over the unit square.
+    #[serde(skip)]
+    integral: F,
+}
+
+impl Environment {
+    /// Load an environment map from a Radiance `.hdr` file.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut environment = Self {
+            path: path.to_path_buf(),
+            yaw: 0.0,
+            intensity: 1.0,
+            width: 0,
+            height: 0,
+            pixels: Arc::new([]),
+            marginal: Arc::new([]),
+            conditional: Arc::new([]),
+            integral: 0.0,
+        };
+        environment.reload()?;
+        Ok(environment)
+    }
+
+    /// (Re)load the image from the path, keeping rotation and intensity. Projects only store
+    /// the path, this is called after a project has been read.
+    pub fn reload(&mut self) -> std::io::Result<()> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(&self.path)?);
+        let (width, height, pixels) = hdr::read_hdr(&mut reader)?;
+        self.set_pixels(width, height, pixels.into_iter().map(Vec3::from).collect());
+        Ok(())
+    }
+
+    /// True once the image is available.
+    pub fn is_loaded(&self) -> bool {
+        !self.pixels.is_empty()
+    }
+
+    /// Set the image and build the sampling distribution, proportional to the luminance
+    /// weighted by the solid angle of the pixel rows.
+    pub fn set_pixels(&mut self, width: usize, height: usize, pixels: Vec<Vec3<F>>) {
+        let mut conditional = vec![0.0; width * height];
+        let mut marginal = vec![0.0; height];
+
+        let mut total = 0.0;
+        for y in 0..height {
+            let sin_theta = (F_PI * (y as F + 0.5) / height as F).sin();
+            let pixels = &pixels[y * width..(y + 1) * width];
+            let row = &mut conditional[y * width..(y + 1) * width];
+
+            let mut sum = 0.0;
+            for (c, pixel) in row.iter_mut().zip(pixels) {
+                sum += weight(*pixel, sin_theta);
+                *c = sum;
+            }
+            if sum > 0.0 {
+                for c in row.iter_mut() {
+                    *c /= sum;
+                }
+            }
+
+            total += sum;
+            marginal[y] = total;
+        }
+
+        if total > 0.0 {
+            for m in &mut marginal {
+                *m /= total;
+            }
+        }
+        self.width = width;
+        self.height = height;
+        self.pixels = pixels.into();
+        self.conditional = conditional.into();
+        self.marginal = marginal.into();
+        self.integral = total / (width * height) as F;
+    }
+
+    /// The radiance arriving from the given direction.
+    pub fn radiance(&self, dir: Vec3<F>) -> Vec3<F> {
+        if !self.is_loaded() {
+            return Vec3::zero();
+        }
+        let (x, y) = self.pixel(self.to_uv(dir));
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    /// Importance sample a direction proportional to the brightness of the map.
+    pub fn sample(&self, rnd: Vec2<F>) -> Option<LightSample> {
+        if !self.is_loaded() || self.integral <= 0.0 {
+            return None;
+        }
+
+        let (y, dv) = sample_cdf(&self.marginal, rnd.y);
+        let row = &self.conditional[y * self.width..(y + 1) * self.width];
+        let (x, du) = sample_cdf(row, rnd.x);
+
+        let uv = Vec2::new(
+            (x as F + du) / self.width as F,
+            (y as F + dv) / self.height as F,
+        );
+        let direction = self.to_dir(uv);
+        let pdf = self.pdf_pixel(x, y);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance: F::MAX,
+            radiance: self.pixels[y * self.width + x] * self.intensity,
+            pdf,
+        })
+    }
+
+    /// The solid angle pdf of `sample` for the given direction.
+    pub fn pdf(&self, dir: Vec3<F>) -> F {
+        if !self.is_loaded() || self.integral <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel(self.to_uv(dir));
+        self.pdf_pixel(x, y)
+    }
+
+    fn pdf_pixel(&self, x: usize, y: usize) -> F {
+        let sin_theta = (F_PI * (y as F + 0.5) / self.height as F).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // The pdf over the unit square, converted to solid angle
+        let pdf_uv = weight(self.pixels[y * self.width + x], sin_theta) / self.integral;
+        pdf_uv / (2.0 * F_PI * F_PI * sin_theta)
+    }
+
+    fn pixel(&self, uv: Vec2<F>) -> (usize, usize) {
+        let x = ((uv.x * self.width as F) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as F) as usize).min(self.height - 1);
+        (x, y)
+    }
+
+    /// Map a direction to map coordinates, u wraps around the up axis, v = 0 is straight up.
+    fn to_uv(&self, dir: Vec3<F>) -> Vec2<F> {
+        let dir = dir.normalized();
+        let phi = dir.x.atan2(-dir.z) + self.yaw;
+        let u = (phi / F_TAU + 0.5).rem_euclid(1.0);
+        let v = dir.y.clamp(-1.0, 1.0).acos() / F_PI;
+        Vec2::new(u, v)
+    }
+
+    fn to_dir(&self, uv: Vec2<F>) -> Vec3<F> {
+        let phi = (uv.x - 0.5) * F_TAU - self.yaw;
+        let theta = uv.y * F_PI;
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+}
+
+/// The sampling weight of a pixel, its luminance times the solid angle factor of its row.
+fn weight(c: Vec3<F>, sin_theta: F) -> F {
+    luminance(c).max(0.0) * sin_theta
+}
+
+/// Pick an index from a normalized CDF, returns the index and the position within it.
+fn sample_cdf(cdf: &[F], r: F) -> (usize, F) {
+    let index = cdf.partition_point(|c| *c <= r).min(cdf.len() - 1);
+    let low = if index > 0 { cdf[index - 1] } else { 0.0 };
+    let width = cdf[index] - low;
+    let offset = if width > 0.0 {
+        ((r - low) / width).clamp(0.0, 0.9999)
+    } else {
+        0.5
+    };
+    (index, offset)
+}
@@ -0,0 +1,86 @@
+use crate::prelude::*;
+use crate::voxel::color::srgb_to_linear;
+use crate::voxel::renderer::medium::henyey_greenstein;
+use vek::{Aabb, Vec3};
+
+/// The number of steps used to integrate the fog density along shadow rays.
+const SHADOW_STEPS: usize = 8;
+
+/// Scene wide height fog plus a uniform atmosphere, filling the bounds of the grid.
+///
+/// The extinction per world unit at a point is `atmosphere` plus `density` below `height`,
+/// fading out exponentially with `falloff` above it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Fog {
+    /// Extinction of the height fog below `height`, per world unit.
+    pub density: F,
+    /// The height below which the fog has its full density.
+    pub height: F,
+    /// How quickly the fog thins out above `height`, 0 fills the whole grid.
+    pub falloff: F,
+    /// Uniform extinction everywhere, for aerial perspective in large scenes.
+    pub atmosphere: F,
+    /// The sRGB scattering color, the remaining light is absorbed.
+    pub color: Vec3<F>,
+    /// Henyey-Greenstein asymmetry, positive values glow around the sun.
+    pub anisotropy: F,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            density: 0.4,
+            height: -1.0,
+            falloff: 1.5,
+            atmosphere: 0.02,
+            color: Vec3::new(0.85, 0.88, 0.92),
+            anisotropy: 0.3,
+        }
+    }
+}
+
+impl Fog {
+    /// The extinction at the point.
+    pub fn extinction(&self, p: Vec3<F>) -> F {
+        let height = if p.y <= self.height {
+            1.0
+        } else {
+            (-self.falloff.max(0.0) * (p.y - self.height)).exp()
+        };
+        (self.atmosphere + self.density * height).max(0.0)
+    }
+
+    /// The linear single scattering albedo.
+    pub fn albedo(&self) -> Vec3<F> {
+        srgb_to_linear(self.color.map(|c| c.clamp(0.0, 1.0)))
+    }
+
+    pub fn phase(&self, cos: F) -> F {
+        henyey_greenstein(cos, self.anisotropy.clamp(-0.99, 0.99))
+    }
+
+    /// The part of the ray between `start` and `end` which lies inside the bounds.
+    pub fn segment(ray: &Ray, bounds: &Aabb<F>, start: F, end: F) -> Option<(F, F)> {
+        let (t_min, t_max) = ray.intersect_aabb(bounds)?;
+        let (start, end) = (start.max(t_min), end.min(t_max));
+        if end > start {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    /// The transmittance along the ray up to `distance`, integrated with a few midpoint
+    /// steps. Used for shadow rays.
+    pub fn transmittance(&self, ray: &Ray, bounds: &Aabb<F>, distance: F) -> F {
+        let Some((start, end)) = Self::segment(ray, bounds, 0.0, distance) else {
+            return 1.0;
+        };
+
+        let step = (end - start) / SHADOW_STEPS as F;
+        let depth: F = (0..SHADOW_STEPS)
+            .map(|i| self.extinction(ray.at(start + (i as F + 0.5) * step)))
+            .sum();
+        (-depth * step).exp()
+    }
+}
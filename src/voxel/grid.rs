@@ -1,15 +1,18 @@
 use crate::prelude::*;
 use rayon::prelude::*;
+use std::sync::Arc;
 use theframework::prelude::FxHashMap;
 
+/// The tiles are shared between clones and copied on write, so a clone of the grid is cheap
+/// and only the edited tiles are duplicated while a render still reads the clone.
 #[derive(Clone)]
 pub struct VoxelGrid {
-    pub tiles: FxHashMap<Coord, Tile>,
+    pub tiles: FxHashMap<Coord, Arc<Tile>>,
     pub density: usize,
     pub density_f: F,
     pub bounds: [F; 3],
 
-    pub preview: Option<Box<VoxelGrid>>,
+    pub preview: Option<Arc<VoxelGrid>>,
 }
 
 impl Default for VoxelGrid {
@@ -20,7 +23,7 @@ impl Default for VoxelGrid {
 
 impl VoxelGrid {
     pub fn new(bounds: [F; 3], density: usize) -> Self {
-        let mut tiles = FxHashMap::default();
+        let mut grid = Self::empty(bounds, density);
 
         let x_tiles = bounds[0].ceil() as i32;
         let y_tiles = bounds[1].ceil() as i32;
@@ -35,13 +38,19 @@ impl VoxelGrid {
                 for tx in 0..x_tiles {
                     let mut tile = Tile::new(density);
                     tile.update_bbox();
-                    tiles.insert((x_start + tx, y_start + ty, z_start + tz), tile);
+                    grid.tiles
+                        .insert((x_start + tx, y_start + ty, z_start + tz), Arc::new(tile));
                 }
             }
         }
 
+        grid
+    }
+
+    /// Create a grid with the given bounds but without allocating any tiles.
+    pub fn empty(bounds: [F; 3], density: usize) -> Self {
         Self {
-            tiles,
+            tiles: FxHashMap::default(),
             density,
             density_f: density as F,
             bounds,
@@ -52,10 +61,37 @@ impl VoxelGrid {
     /// Update the bounding boxes of the tiles (needed after editing)
     pub fn update_bboxes(&mut self) {
         self.tiles.par_iter_mut().for_each(|(_, tile)| {
-            tile.update_bbox();
+            // Only copy tiles still shared with a clone if their bounds changed
+            let (has_voxels, bbox) = tile.compute_bbox();
+            if has_voxels != tile.has_voxels || bbox != tile.bbox {
+                let tile = Arc::make_mut(tile);
+                tile.has_voxels = has_voxels;
+                tile.bbox = bbox;
+            }
         });
     }
 
+    /// True if both grids share all their tiles, no voxel changed since one was cloned from
+    /// the other. The preview is not compared.
+    pub fn shares_tiles(&self, other: &VoxelGrid) -> bool {
+        self.density == other.density
+            && self.tiles.len() == other.tiles.len()
+            && self
+                .tiles
+                .iter()
+                .all(|(key, tile)| other.tiles.get(key).is_some_and(|o| Arc::ptr_eq(tile, o)))
+    }
+
+    /// Grow the bounds so that they contain all tiles.
+    pub fn update_bounds(&mut self) {
+        for key in self.tiles.keys() {
+            let extent = [key.0, key.1, key.2].map(|k| (k.abs().max((k + 1).abs()) * 2) as F);
+            for (bound, extent) in self.bounds.iter_mut().zip(extent) {
+                *bound = bound.max(extent);
+            }
+        }
+    }
+
     /// Get a voxel at the given world coordinate
     #[inline(always)]
     pub fn get(&self, wc: Vec3<f32>) -> Option<u8> {
@@ -68,7 +104,7 @@ impl VoxelGrid {
     pub fn set(&mut self, wc: Vec3<f32>, mat: u8) {
         let (tile_key, local_key) = self.to_tile_coord(wc);
         if let Some(tile) = self.tiles.get_mut(&tile_key) {
-            tile.set(local_key, mat);
+            Arc::make_mut(tile).set(local_key, mat);
         }
     }
 
@@ -76,10 +112,55 @@ impl VoxelGrid {
     #[inline(always)]
     pub fn set_create(&mut self, wc: Vec3<f32>, mat: u8) {
         let (tile_key, local_key) = self.to_tile_coord(wc);
-        self.tiles
-            .entry(tile_key)
-            .or_insert_with(|| Tile::new(self.density))
-            .set(local_key, mat);
+        self.tile_mut(tile_key).set(local_key, mat);
+    }
+
+    /// Get a voxel at the given global voxel coordinate (tile key * density + local key).
+    #[inline(always)]
+    pub fn get_voxel(&self, v: Vec3<i32>) -> Option<u8> {
+        let d = self.density as i32;
+        let tile_key = (v.x.div_euclid(d), v.y.div_euclid(d), v.z.div_euclid(d));
+        let local_key = (v.x.rem_euclid(d), v.y.rem_euclid(d), v.z.rem_euclid(d));
+        self.tiles.get(&tile_key)?.get(local_key)
+    }
+
+    /// Set a voxel at the given global voxel coordinate and create a new tile if necessary.
+    #[inline(always)]
+    pub fn set_voxel_create(&mut self, v: Vec3<i32>, mat: u8) {
+        let d = self.density as i32;
+        let tile_key = (v.x.div_euclid(d), v.y.div_euclid(d), v.z.div_euclid(d));
+        let local_key = (v.x.rem_euclid(d), v.y.rem_euclid(d), v.z.rem_euclid(d));
+        self.tile_mut(tile_key).set(local_key, mat);
+    }
+
+    /// Calls the closure for every set voxel with its global voxel coordinate and material.
+    pub fn for_each_voxel<C>(&self, mut callback: C)
+    where
+        C: FnMut(Vec3<i32>, u8),
+    {
+        let d = self.density as i32;
+        for (key, tile) in &self.tiles {
+            let origin = Vec3::new(key.0, key.1, key.2) * d;
+            for (index, voxel) in tile.voxels.iter().enumerate() {
+                if let Some(mat) = voxel {
+                    let index = index as i32;
+                    let local = Vec3::new(index % d, (index / d) % d, index / (d * d));
+                    callback(origin + local, *mat);
+                }
+            }
+        }
+    }
+
+    /// The tile for writing, created if necessary. A tile still shared with a clone of the
+    /// grid is copied first.
+    #[inline(always)]
+    pub fn tile_mut(&mut self, tile_key: Coord) -> &mut Tile {
+        let density = self.density;
+        Arc::make_mut(
+            self.tiles
+                .entry(tile_key)
+                .or_insert_with(|| Arc::new(Tile::new(density))),
+        )
     }
 
     /// Converts the hit keys to a world coordinate
@@ -112,7 +193,7 @@ impl VoxelGrid {
 
     /// World-space Aabb of the whole grid
     #[inline]
-    fn bbox(&self) -> Aabb<F> {
+    pub fn bbox(&self) -> Aabb<F> {
         let h = Vec3::from(self.bounds) * 0.5;
         Aabb { min: -h, max: h }
     }
@@ -124,11 +205,8 @@ impl VoxelGrid {
             None => return,
         };
 
-        for (tile_key, src_tile) in preview.tiles {
-            let dst_tile = self
-                .tiles
-                .entry(tile_key)
-                .or_insert_with(|| Tile::new(self.density));
+        for (tile_key, src_tile) in &preview.tiles {
+            let dst_tile = self.tile_mut(*tile_key);
 
             let d = src_tile.density as i32; // side length per axis
             let area = d * d; // d², pre-compute
@@ -223,4 +301,126 @@ impl VoxelGrid {
             ..Default::default()
         }
     }
+
+    /// Follow a ray which starts inside a run of voxels with the given material to where it
+    /// leaves them. Used for rays refracted into transmissive voxels.
+    ///
+    /// The hit is `HitType::Voxel(material)` on the face towards an empty cell and the voxel
+    /// of another material if the run ends there, the normal is the step direction.
+    /// `HitType::Outside` is returned when the ray leaves the grid bounds.
+    pub fn exit(&self, ray: &Ray, material: u8) -> HitRecord {
+        // A NaN or infinite ray never reaches t_max, treat it as leaving the grid
+        let finite = |v: Vec3<F>| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        if !finite(ray.origin) || !finite(ray.dir) {
+            return HitRecord {
+                hit: HitType::Outside,
+                hitpoint: ray.origin,
+                ..Default::default()
+            };
+        }
+
+        let t_max = match ray.intersect_aabb(&self.bbox()) {
+            Some((_, t_max)) => t_max * self.density_f,
+            None => 0.0,
+        };
+
+        // A ray crosses at most one cell boundary per voxel of the extent on each axis
+        let max_steps = self
+            .bounds
+            .iter()
+            .map(|b| (b * self.density_f).ceil() as usize + 1)
+            .sum::<usize>();
+
+        // Traverse in voxel units
+        let p = ray.origin * self.density_f;
+        let rd = ray.dir;
+        let mut cell = p.map(|v| v.floor());
+        let step = rd.map(|v| v.signum());
+        let t_delta = rd.map(|v| if v != 0.0 { (1.0 / v).abs() } else { F::MAX });
+        let mut t_next = Vec3::new(
+            Self::first_crossing(p.x, cell.x, rd.x),
+            Self::first_crossing(p.y, cell.y, rd.y),
+            Self::first_crossing(p.z, cell.z, rd.z),
+        );
+
+        let mut steps = 0;
+        loop {
+            let axis = if t_next.x < t_next.y {
+                if t_next.x < t_next.z { 0 } else { 2 }
+            } else if t_next.y < t_next.z {
+                1
+            } else {
+                2
+            };
+
+            let t = t_next[axis];
+            if t >= t_max || steps > max_steps {
+                return HitRecord {
+                    hit: HitType::Outside,
+                    hitpoint: ray.at(t_max / self.density_f),
+                    distance: t_max / self.density_f,
+                    ..Default::default()
+                };
+            }
+
+            steps += 1;
+
+            let previous = cell.map(|v| v as i32);
+            cell[axis] += step[axis];
+            t_next[axis] += t_delta[axis];
+
+            let voxel = cell.map(|v| v as i32);
+            let next = self.get_voxel(voxel);
+            if next == Some(material) {
+                continue;
+            }
+
+            let mut normal = Vec3::zero();
+            normal[axis] = step[axis];
+
+            // Report the voxel which is hit, or the one which is left
+            let key = if next.is_some() { voxel } else { previous };
+            let d = self.density as i32;
+            return HitRecord {
+                hit: HitType::Voxel(next.unwrap_or(material)),
+                hitpoint: ray.at(t / self.density_f),
+                normal,
+                distance: t / self.density_f,
+                tile_key: (
+                    key.x.div_euclid(d),
+                    key.y.div_euclid(d),
+                    key.z.div_euclid(d),
+                ),
+                local_key: (
+                    key.x.rem_euclid(d),
+                    key.y.rem_euclid(d),
+                    key.z.rem_euclid(d),
+                ),
+                ..Default::default()
+            };
+        }
+    }
+
+    /// The ray parameter at which a ray starting at `p` first crosses a cell boundary on one
+    /// axis.
+    #[inline(always)]
+    fn first_crossing(p: F, cell: F, dir: F) -> F {
+        if dir > 0.0 {
+            (cell + 1.0 - p) / dir
+        } else if dir < 0.0 {
+            (cell - p) / dir
+        } else {
+            F::MAX
+        }
+    }
+
+    /// Returns true if a voxel blocks the ray before `max_distance`. Used for shadow rays.
+    pub fn occluded(&self, ray: &Ray, max_distance: F) -> bool {
+        let hit = self.dda(ray);
+        if let HitType::Voxel(_) = hit.hit {
+            (hit.hitpoint - ray.origin).magnitude_squared() < max_distance * max_distance
+        } else {
+            false
+        }
+    }
 }
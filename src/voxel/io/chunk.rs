@@ -0,0 +1,466 @@
+//! Compact binary format for a `VoxelGrid`.
+//!
+//! Layout (all values little-endian):
+//!
+//! * Header: magic `SZVG`, format version (u32), density (u32), bounds (3 x f32), tile count (u32).
+//! * Per non-empty tile: tile key (3 x i32), encoding (u8), payload size (u32), payload.
+//!
+//! Each tile is stored either run-length encoded or bit-packed against a local palette,
+//! whichever is smaller.
+
+use super::{
+    read_f32, read_i32, read_u8, read_u32, read_varint, write_f32, write_i32, write_u32,
+    write_varint,
+};
+use crate::prelude::*;
+use rayon::prelude::*;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"SZVG";
+const VERSION: u32 = 1;
+
+/// Runs of (length, voxel), stored as varint `(length << 1) | filled` followed by the material.
+const ENCODING_RLE: u8 = 0;
+/// A local palette of the used values followed by bit-packed palette indices.
+const ENCODING_PACKED: u8 = 1;
+
+/// Marks an empty voxel in the local palette of a packed tile.
+const PACKED_EMPTY: u16 = 256;
+
+/// The largest tile density we accept, a tile holds `density³` voxels.
+const MAX_DENSITY: usize = 256;
+
+/// Write the grid to the writer. Empty tiles are skipped.
+pub fn write_grid<W: Write>(grid: &VoxelGrid, writer: &mut W) -> Result<()> {
+    let mut keys: Vec<&Coord> = grid
+        .tiles
+        .iter()
+        .filter(|(_, tile)| tile.voxels.iter().any(|v| v.is_some()))
+        .map(|(key, _)| key)
+        .collect();
+    keys.sort();
+
+    let encoded: Vec<(u8, Vec<u8>)> = keys
+        .par_iter()
+        .map(|key| encode_tile(&grid.tiles[*key]))
+        .collect();
+
+    writer.write_all(MAGIC)?;
+    write_u32(writer, VERSION)?;
+    write_u32(writer, grid.density as u32)?;
+    for b in grid.bounds {
+        write_f32(writer, b)?;
+    }
+    write_u32(writer, keys.len() as u32)?;
+
+    for (key, (encoding, payload)) in keys.iter().zip(encoded) {
+        write_i32(writer, key.0)?;
+        write_i32(writer, key.1)?;
+        write_i32(writer, key.2)?;
+        writer.write_all(&[encoding])?;
+        write_u32(writer, payload.len() as u32)?;
+        writer.write_all(&payload)?;
+    }
+
+    Ok(())
+}
+
+/// Read a grid written by `write_grid`.
+pub fn read_grid<R: Read>(reader: &mut R) -> Result<VoxelGrid> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a voxel grid chunk"));
+    }
+
+    let version = read_u32(reader)?;
+    if version > VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported voxel grid version {}", version),
+        ));
+    }
+
+    let density = read_u32(reader)? as usize;
+    if density == 0 || density > MAX_DENSITY {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid tile density {}", density),
+        ));
+    }
+    let bounds = [read_f32(reader)?, read_f32(reader)?, read_f32(reader)?];
+    let count = read_u32(reader)?;
+
+    // Neither encoding needs more than two bytes per voxel plus the local palette
+    let max_payload = 2 * density * density * density + 1024;
+
+    // The counts come from the file, the buffers only grow with the data actually read
+    let mut chunks = vec![];
+    for _ in 0..count {
+        let key = (read_i32(reader)?, read_i32(reader)?, read_i32(reader)?);
+        let encoding = read_u8(reader)?;
+        let size = read_u32(reader)? as usize;
+        if size > max_payload {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid tile payload size {}", size),
+            ));
+        }
+        let mut payload = vec![];
+        reader
+            .by_ref()
+            .take(size as u64)
+            .read_to_end(&mut payload)?;
+        if payload.len() != size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated tile payload",
+            ));
+        }
+        chunks.push((key, encoding, payload));
+    }
+
+    let tiles: Vec<(Coord, Tile)> = chunks
+        .into_par_iter()
+        .map(|(key, encoding, payload)| {
+            decode_tile(encoding, &payload, density).map(|tile| (key, tile))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut grid = VoxelGrid::empty(bounds, density);
+    grid.tiles
+        .extend(tiles.into_iter().map(|(key, tile)| (key, Arc::new(tile))));
+
+    Ok(grid)
+}
+
+/// Encode the tile with the smaller of the two encodings.
+fn encode_tile(tile: &Tile) -> (u8, Vec<u8>) {
+    let rle = encode_rle(&tile.voxels);
+    let packed = encode_packed(&tile.voxels);
+
+    if rle.len() <= packed.len() {
+        (ENCODING_RLE, rle)
+    } else {
+        (ENCODING_PACKED, packed)
+    }
+}
+
+fn decode_tile(encoding: u8, payload: &[u8], density: usize) -> Result<Tile> {
+    let mut tile = Tile::new(density);
+
+    match encoding {
+        ENCODING_RLE => decode_rle(payload, &mut tile.voxels)?,
+        ENCODING_PACKED => decode_packed(payload, &mut tile.voxels)?,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown tile encoding {}", encoding),
+            ));
+        }
+    }
+
+    tile.update_bbox();
+    Ok(tile)
+}
+
+fn encode_rle(voxels: &[Option<u8>]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut index = 0;
+
+    while index < voxels.len() {
+        let value = voxels[index];
+        let mut length = 1;
+        while index + length < voxels.len() && voxels[index + length] == value {
+            length += 1;
+        }
+
+        let filled = value.is_some() as u64;
+        _ = write_varint(&mut out, ((length as u64) << 1) | filled);
+        if let Some(mat) = value {
+            out.push(mat);
+        }
+
+        index += length;
+    }
+
+    out
+}
+
+fn decode_rle(mut payload: &[u8], voxels: &mut [Option<u8>]) -> Result<()> {
+    let mut index = 0;
+
+    while !payload.is_empty() {
+        let run = read_varint(&mut payload)?;
+        let length = (run >> 1) as usize;
+        let value = if run & 1 == 1 {
+            Some(read_u8(&mut payload)?)
+        } else {
+            None
+        };
+
+        if index + length > voxels.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "tile run out of bounds"));
+        }
+        voxels[index..index + length].fill(value);
+        index += length;
+    }
+
+    if index != voxels.len() {
+        return Err(Error::new(ErrorKind::InvalidData, "incomplete tile runs"));
+    }
+
+    Ok(())
+}
+
+fn encode_packed(voxels: &[Option<u8>]) -> Vec<u8> {
+    let to_entry = |v: &Option<u8>| v.map(|m| m as u16).unwrap_or(PACKED_EMPTY);
+
+    let mut used = [false; 257];
+    for v in voxels {
+        used[to_entry(v) as usize] = true;
+    }
+
+    let entries: Vec<u16> = (0..257_u16).filter(|e| used[*e as usize]).collect();
+    let mut lookup = [0_u16; 257];
+    for (index, entry) in entries.iter().enumerate() {
+        lookup[*entry as usize] = index as u16;
+    }
+
+    let bits = bits_for(entries.len());
+
+    let mut out = Vec::with_capacity(2 + entries.len() * 2 + (voxels.len() * bits).div_ceil(8));
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for entry in &entries {
+        out.extend_from_slice(&entry.to_le_bytes());
+    }
+
+    let mut acc = 0_u32;
+    let mut acc_bits = 0;
+    for v in voxels {
+        acc |= (lookup[to_entry(v) as usize] as u32) << acc_bits;
+        acc_bits += bits;
+        while acc_bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    }
+    if acc_bits > 0 {
+        out.push(acc as u8);
+    }
+
+    out
+}
+
+fn decode_packed(payload: &[u8], voxels: &mut [Option<u8>]) -> Result<()> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "invalid packed tile");
+
+    if payload.len() < 2 {
+        return Err(invalid());
+    }
+    let count = u16::from_le_bytes([payload[0], payload[1]]) as usize;
+    if count == 0 || count > 257 || payload.len() < 2 + count * 2 {
+        return Err(invalid());
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = u16::from_le_bytes([payload[2 + i * 2], payload[3 + i * 2]]);
+        entries.push(match entry {
+            PACKED_EMPTY => None,
+            e if e < 256 => Some(e as u8),
+            _ => return Err(invalid()),
+        });
+    }
+
+    let bits = bits_for(count);
+    let data = &payload[2 + count * 2..];
+    if data.len() < (voxels.len() * bits).div_ceil(8) {
+        return Err(invalid());
+    }
+
+    let mask = (1_u32 << bits) - 1;
+    let mut acc = 0_u32;
+    let mut acc_bits = 0;
+    let mut data = data.iter();
+
+    for voxel in voxels.iter_mut() {
+        while acc_bits < bits {
+            acc |= (*data.next().ok_or_else(invalid)? as u32) << acc_bits;
+            acc_bits += 8;
+        }
+        let index = (acc & mask) as usize;
+        acc >>= bits;
+        acc_bits -= bits;
+
+        *voxel = *entries.get(index).ok_or_else(invalid)?;
+    }
+
+    Ok(())
+}
+
+/// The number of bits needed to index `count` palette entries (at least one).
+fn bits_for(count: usize) -> usize {
+    let mut bits = 1;
+    while (1 << bits) < count {
+        bits += 1;
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DENSITY: usize = 8;
+
+    fn round_trip(grid: &VoxelGrid) -> VoxelGrid {
+        let mut data = vec![];
+        write_grid(grid, &mut data).unwrap();
+        read_grid(&mut data.as_slice()).unwrap()
+    }
+
+    fn grid_with(voxels: impl Fn(usize) -> Option<u8>) -> VoxelGrid {
+        let mut grid = VoxelGrid::empty([1.0, 1.0, 1.0], DENSITY);
+        let tile = grid.tile_mut((0, 0, -1));
+        for (index, voxel) in tile.voxels.iter_mut().enumerate() {
+            *voxel = voxels(index);
+        }
+        grid
+    }
+
+    fn assert_same_voxels(a: &VoxelGrid, b: &VoxelGrid) {
+        assert_eq!(a.density, b.density);
+        assert_eq!(a.bounds, b.bounds);
+        for (key, tile) in &b.tiles {
+            assert_eq!(a.tiles[key].voxels, tile.voxels);
+        }
+    }
+
+    /// A grid header announcing `count` tiles.
+    fn header(density: u32, count: u32) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(MAGIC);
+        write_u32(&mut data, VERSION).unwrap();
+        write_u32(&mut data, density).unwrap();
+        for _ in 0..3 {
+            write_f32(&mut data, 1.0).unwrap();
+        }
+        write_u32(&mut data, count).unwrap();
+        data
+    }
+
+    /// A grid with a single tile with the given encoding, payload size and payload.
+    fn single_tile(density: u32, encoding: u8, size: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = header(density, 1);
+        for _ in 0..3 {
+            write_i32(&mut data, 0).unwrap();
+        }
+        data.push(encoding);
+        write_u32(&mut data, size).unwrap();
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn read_error(data: &[u8]) -> ErrorKind {
+        read_grid(&mut &data[..]).err().unwrap().kind()
+    }
+
+    #[test]
+    fn empty_tiles_are_skipped() {
+        let grid = grid_with(|_| None);
+        let read = round_trip(&grid);
+        assert!(read.tiles.is_empty());
+
+        let tile = &grid.tiles[&(0, 0, -1)];
+        let (encoding, payload) = encode_tile(tile);
+        assert_eq!(
+            decode_tile(encoding, &payload, DENSITY).unwrap().voxels,
+            tile.voxels
+        );
+    }
+
+    #[test]
+    fn full_tile_round_trips() {
+        let grid = grid_with(|_| Some(7));
+        let read = round_trip(&grid);
+        assert_eq!(read.tiles.len(), 1);
+        assert_same_voxels(&grid, &read);
+    }
+
+    #[test]
+    fn mixed_tile_round_trips() {
+        // Long runs favour RLE, noise favours bit-packing
+        let runs = grid_with(|index| (index % 100 < 60).then_some((index / 100) as u8));
+        assert_eq!(encode_tile(&runs.tiles[&(0, 0, -1)]).0, ENCODING_RLE);
+        assert_same_voxels(&runs, &round_trip(&runs));
+
+        let noise = grid_with(|index| {
+            let hash = (index * 2_654_435_761) >> 7;
+            (hash % 3 != 0).then_some((hash % 5) as u8)
+        });
+        assert_eq!(encode_tile(&noise.tiles[&(0, 0, -1)]).0, ENCODING_PACKED);
+        assert_same_voxels(&noise, &round_trip(&noise));
+    }
+
+    #[test]
+    fn density_limits() {
+        let empty = |density: u32| read_grid(&mut header(density, 0).as_slice());
+        assert_eq!(empty(1).unwrap().density, 1);
+        assert_eq!(empty(MAX_DENSITY as u32).unwrap().density, MAX_DENSITY);
+        assert_eq!(empty(0).err().unwrap().kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            empty(MAX_DENSITY as u32 + 1).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn bad_payloads_are_rejected() {
+        let voxels = (DENSITY * DENSITY * DENSITY) as u64;
+        let mut full_run = vec![];
+        write_varint(&mut full_run, voxels << 1).unwrap();
+        let mut long_run = vec![];
+        write_varint(&mut long_run, (voxels + 1) << 1).unwrap();
+        let mut short_run = vec![];
+        write_varint(&mut short_run, (voxels - 1) << 1).unwrap();
+
+        let density = DENSITY as u32;
+        let tile = |encoding: u8, payload: &[u8]| {
+            single_tile(density, encoding, payload.len() as u32, payload)
+        };
+
+        assert!(read_grid(&mut tile(ENCODING_RLE, &full_run).as_slice()).is_ok());
+        assert_eq!(
+            read_error(&tile(ENCODING_RLE, &long_run)),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_error(&tile(ENCODING_RLE, &short_run)),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(read_error(&tile(2, &full_run)), ErrorKind::InvalidData);
+
+        // Packed: no entries, an unknown entry and missing indices
+        assert_eq!(
+            read_error(&tile(ENCODING_PACKED, &[0, 0])),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_error(&tile(ENCODING_PACKED, &[1, 0, 1, 1])),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_error(&tile(ENCODING_PACKED, &[1, 0, 3, 0, 0])),
+            ErrorKind::InvalidData
+        );
+
+        // Sizes beyond the limit of the density and payloads shorter than their size
+        let oversized = single_tile(density, ENCODING_RLE, u32::MAX, &full_run);
+        assert_eq!(read_error(&oversized), ErrorKind::InvalidData);
+        let truncated = single_tile(density, ENCODING_RLE, 100, &full_run);
+        assert_eq!(read_error(&truncated), ErrorKind::UnexpectedEof);
+    }
+}
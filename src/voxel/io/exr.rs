@@ -0,0 +1,95 @@
+//! Single-layer OpenEXR writer for float RGBA images.
+//!
+//! The file is a scanline image with 32-bit float A, B, G and R channels and no compression,
+//! which every EXR reader supports.
+
+use super::{write_f32, write_i32, write_u32};
+use std::io::{Result, Write};
+
+const MAGIC: u32 = 20000630;
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+/// Channel names in the (alphabetical) order EXR requires, with their RGBA offset.
+const CHANNELS: [(&str, usize); 4] = [("A", 3), ("B", 2), ("G", 1), ("R", 0)];
+
+/// Write linear RGBA pixels, top row first, as an uncompressed OpenEXR file.
+pub fn write_exr<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[f32],
+) -> Result<()> {
+    let mut header = vec![];
+    write_u32(&mut header, MAGIC)?;
+    write_u32(&mut header, VERSION)?;
+
+    let mut channels = vec![];
+    for (name, _) in CHANNELS {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        write_i32(&mut channels, PIXEL_TYPE_FLOAT)?;
+        channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        write_i32(&mut channels, 1)?;
+        write_i32(&mut channels, 1)?;
+    }
+    channels.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channels)?;
+
+    write_attribute(&mut header, "compression", "compression", &[0])?;
+
+    let mut window = vec![];
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        write_i32(&mut window, v)?;
+    }
+    write_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_attribute(&mut header, "displayWindow", "box2i", &window)?;
+
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    )?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    )?;
+    header.push(0);
+
+    writer.write_all(&header)?;
+
+    // One scanline per block: y, data size, then every channel of the line
+    let line_size = width * CHANNELS.len() * 4;
+    let block_size = 8 + line_size;
+    let table_end = header.len() + height * 8;
+    for y in 0..height {
+        writer.write_all(&((table_end + y * block_size) as u64).to_le_bytes())?;
+    }
+
+    for y in 0..height {
+        write_i32(writer, y as i32)?;
+        write_i32(writer, line_size as i32)?;
+        for (_, offset) in CHANNELS {
+            for x in 0..width {
+                write_f32(writer, pixels[(y * width + x) * 4 + offset])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) -> Result<()> {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    write_i32(header, value.len() as i32)?;
+    header.extend_from_slice(value);
+    Ok(())
+}
@@ -0,0 +1,261 @@
+//! Binary glTF 2.0 (.glb) export of a greedy mesh.
+//!
+//! Every used palette index becomes one primitive with a metallicRoughness material. Emission
+//! beyond 1.0, transmission, ior, clearcoat and sheen are written with the respective KHR
+//! extensions when the material makes use of them.
+
+use super::mesh::{Mesh, Quad};
+use super::write_u32;
+use crate::prelude::*;
+use serde_json::{Map, Value, json};
+use std::io::{Error, ErrorKind, Result, Write};
+
+const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A; // "JSON"
+const CHUNK_BIN: u32 = 0x004E_4942; // "BIN\0"
+
+const COMPONENT_UINT: u32 = 5125;
+const COMPONENT_FLOAT: u32 = 5126;
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// The index of refraction glTF assumes without `KHR_materials_ior`.
+const GLTF_DEFAULT_IOR: F = 1.5;
+
+/// Write the mesh as a binary glTF file. An empty mesh is an error, glTF requires at least
+/// one primitive per mesh.
+pub fn export_glb<W: Write>(writer: &mut W, mesh: &Mesh, palette: &Palette) -> Result<()> {
+    if mesh.quads.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the grid has no voxels",
+        ));
+    }
+
+    let mut bin: Vec<u8> = vec![];
+    let mut buffer_views = vec![];
+    let mut accessors = vec![];
+    let mut primitives = vec![];
+    let mut materials = vec![];
+    let mut extensions_used: Vec<&str> = vec![];
+
+    for index in mesh.materials() {
+        let quads: Vec<&Quad> = mesh
+            .quads
+            .iter()
+            .filter(|quad| quad.material == index)
+            .collect();
+
+        let mut positions = Vec::with_capacity(quads.len() * 4);
+        let mut normals = Vec::with_capacity(quads.len() * 4);
+        let mut indices = Vec::with_capacity(quads.len() * 6);
+        for quad in &quads {
+            let base = positions.len() as u32;
+            for corner in quad.corners() {
+                positions.push(mesh.position(corner));
+                normals.push(quad.normal());
+            }
+            for triangle in Quad::triangles() {
+                indices.extend(triangle.map(|corner| base + corner as u32));
+            }
+        }
+
+        let mut min = Vec3::broadcast(F::MAX);
+        let mut max = Vec3::broadcast(F::MIN);
+        for p in &positions {
+            min = Vec3::partial_min(min, *p);
+            max = Vec3::partial_max(max, *p);
+        }
+
+        let position_view = push_view(
+            &mut bin,
+            &mut buffer_views,
+            positions.iter().flat_map(|p| [p.x, p.y, p.z]),
+            TARGET_ARRAY_BUFFER,
+        );
+        let normal_view = push_view(
+            &mut bin,
+            &mut buffer_views,
+            normals.iter().flat_map(|n| [n.x, n.y, n.z]),
+            TARGET_ARRAY_BUFFER,
+        );
+        let index_view = buffer_views.len();
+        buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": bin.len(),
+            "byteLength": indices.len() * 4,
+            "target": TARGET_ELEMENT_ARRAY_BUFFER,
+        }));
+        for i in &indices {
+            bin.extend_from_slice(&i.to_le_bytes());
+        }
+
+        let position_accessor = accessors.len();
+        accessors.push(json!({
+            "bufferView": position_view,
+            "componentType": COMPONENT_FLOAT,
+            "count": positions.len(),
+            "type": "VEC3",
+            "min": [min.x, min.y, min.z],
+            "max": [max.x, max.y, max.z],
+        }));
+        accessors.push(json!({
+            "bufferView": normal_view,
+            "componentType": COMPONENT_FLOAT,
+            "count": normals.len(),
+            "type": "VEC3",
+        }));
+        accessors.push(json!({
+            "bufferView": index_view,
+            "componentType": COMPONENT_UINT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+
+        primitives.push(json!({
+            "attributes": {
+                "POSITION": position_accessor,
+                "NORMAL": position_accessor + 1,
+            },
+            "indices": position_accessor + 2,
+            "material": materials.len(),
+        }));
+        materials.push(material_json(
+            index,
+            &palette.materials[index as usize],
+            &mut extensions_used,
+        ));
+    }
+
+    let mut root = json!({
+        "asset": { "version": "2.0", "generator": "Shape-Z" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0, "name": "Voxels" }],
+        "meshes": [{ "primitives": primitives }],
+        "materials": materials,
+        "accessors": accessors,
+        "bufferViews": buffer_views,
+        "buffers": [{ "byteLength": bin.len() }],
+    });
+    if !extensions_used.is_empty() {
+        extensions_used.sort();
+        extensions_used.dedup();
+        root["extensionsUsed"] = json!(extensions_used);
+    }
+
+    let mut json = serde_json::to_vec(&root)?;
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    write_u32(writer, GLB_MAGIC)?;
+    write_u32(writer, GLB_VERSION)?;
+    write_u32(writer, (12 + 8 + json.len() + 8 + bin.len()) as u32)?;
+    write_u32(writer, json.len() as u32)?;
+    write_u32(writer, CHUNK_JSON)?;
+    writer.write_all(&json)?;
+    write_u32(writer, bin.len() as u32)?;
+    write_u32(writer, CHUNK_BIN)?;
+    writer.write_all(&bin)?;
+
+    Ok(())
+}
+
+/// Append the floats as a new buffer view and return the index of the view.
+fn push_view<I: Iterator<Item = F>>(
+    bin: &mut Vec<u8>,
+    views: &mut Vec<Value>,
+    values: I,
+    target: u32,
+) -> usize {
+    let offset = bin.len();
+    for v in values {
+        bin.extend_from_slice(&v.to_le_bytes());
+    }
+    views.push(json!({
+        "buffer": 0,
+        "byteOffset": offset,
+        "byteLength": bin.len() - offset,
+        "target": target,
+    }));
+    views.len() - 1
+}
+
+/// Map a palette material to a glTF material, registering the extensions it uses. Extensions
+/// are only written for fields which differ from the glTF defaults.
+fn material_json<'a>(index: u8, material: &Material, used: &mut Vec<&'a str>) -> Value {
+    let base = material.base_color_linear();
+
+    let mut value = json!({
+        "name": format!("palette_{}", index),
+        "pbrMetallicRoughness": {
+            "baseColorFactor": [base.x, base.y, base.z, 1.0],
+            "metallicFactor": material.metallic,
+            "roughnessFactor": material.roughness,
+        },
+    });
+
+    let mut extensions = Map::new();
+    let mut add = |name: &'a str, ext: Value| {
+        used.push(name);
+        extensions.insert(name.to_string(), ext);
+    };
+
+    // glTF limits the emissive factor to 1.0, the rest goes into the strength extension
+    let emission = material.emission_color * material.emission_strength;
+    let peak = emission.reduce_partial_max();
+    if peak > 0.0 {
+        let factor = emission / peak.max(1.0);
+        value["emissiveFactor"] = json!([factor.x, factor.y, factor.z]);
+        if peak > 1.0 {
+            add(
+                "KHR_materials_emissive_strength",
+                json!({ "emissiveStrength": peak }),
+            );
+        }
+    }
+
+    if material.transmission > 0.0 {
+        add(
+            "KHR_materials_transmission",
+            json!({ "transmissionFactor": material.transmission }),
+        );
+    }
+
+    if material.ior != GLTF_DEFAULT_IOR {
+        add("KHR_materials_ior", json!({ "ior": material.ior }));
+    }
+
+    if material.clearcoat > 0.0 {
+        add(
+            "KHR_materials_clearcoat",
+            json!({
+                "clearcoatFactor": material.clearcoat,
+                "clearcoatRoughnessFactor": 1.0 - material.clearcoat_gloss,
+            }),
+        );
+    }
+
+    if material.sheen > 0.0 {
+        let tint = Vec3::one() + (base - Vec3::one()) * material.sheen_tint;
+        let color = tint * material.sheen;
+        add(
+            "KHR_materials_sheen",
+            json!({
+                "sheenColorFactor": [color.x, color.y, color.z],
+                "sheenRoughnessFactor": material.roughness,
+            }),
+        );
+    }
+
+    if !extensions.is_empty() {
+        value["extensions"] = Value::Object(extensions);
+    }
+
+    value
+}
@@ -0,0 +1,232 @@
+//! Radiance `.hdr` (RGBE) reader and writer for float images.
+//!
+//! The format has no alpha channel, alpha is dropped. Scanlines are run-length encoded.
+
+use std::io::{BufRead, Error, ErrorKind, Result, Write};
+
+/// Write linear RGBA pixels (alpha is ignored) as an RLE compressed Radiance file.
+pub fn write_hdr<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[f32],
+) -> Result<()> {
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    let mut scanline = vec![[0_u8; 4]; width];
+    let mut channel = vec![0_u8; width];
+    let mut out = vec![];
+
+    for y in 0..height {
+        for (x, rgbe) in scanline.iter_mut().enumerate() {
+            let i = (y * width + x) * 4;
+            *rgbe = to_rgbe(pixels[i], pixels[i + 1], pixels[i + 2]);
+        }
+
+        out.clear();
+        if !(8..=0x7FFF).contains(&width) {
+            // Too short or too long for RLE, write flat
+            for rgbe in &scanline {
+                out.extend_from_slice(rgbe);
+            }
+        } else {
+            out.extend_from_slice(&[2, 2, (width >> 8) as u8, (width & 0xFF) as u8]);
+            for c in 0..4 {
+                for (value, rgbe) in channel.iter_mut().zip(&scanline) {
+                    *value = rgbe[c];
+                }
+                encode_channel(&channel, &mut out);
+            }
+        }
+        writer.write_all(&out)?;
+    }
+
+    Ok(())
+}
+
+/// Read a Radiance file, returns the width, height and linear RGB pixels (top row first).
+/// Only the standard `-Y h +X w` orientation is supported.
+pub fn read_hdr<R: BufRead>(reader: &mut R) -> Result<(usize, usize, Vec<[f32; 3]>)> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a Radiance file"));
+    }
+
+    // Header lines until an empty line, then the resolution
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("missing resolution"));
+        }
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(format) = header.strip_prefix("FORMAT=")
+            && format != "32-bit_rle_rgbe"
+        {
+            return Err(invalid("unsupported Radiance pixel format"));
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match parts.as_slice() {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| invalid("invalid height"))?,
+            w.parse::<usize>().map_err(|_| invalid("invalid width"))?,
+        ),
+        _ => return Err(invalid("unsupported Radiance orientation")),
+    };
+    if width == 0 || height == 0 || width > 0x7FFF || height > 0x7FFF {
+        return Err(invalid("invalid Radiance resolution"));
+    }
+
+    // Grow with the scanlines actually read, the resolution is not trusted
+    let mut pixels = vec![];
+    let mut scanline = vec![[0_u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(reader, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|rgbe| from_rgbe(*rgbe)));
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// Read one scanline, either RLE (new style) or flat.
+fn read_scanline<R: BufRead>(reader: &mut R, scanline: &mut [[u8; 4]]) -> Result<()> {
+    let width = scanline.len();
+    let mut first = [0_u8; 4];
+    reader.read_exact(&mut first)?;
+
+    let rle = first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+    if !rle || !(8..=0x7FFF).contains(&width) {
+        scanline[0] = first;
+        for rgbe in scanline.iter_mut().skip(1) {
+            reader.read_exact(rgbe)?;
+        }
+        return Ok(());
+    }
+
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Radiance scanline width mismatch",
+        ));
+    }
+
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0_u8; 1];
+            reader.read_exact(&mut count)?;
+            let count = count[0] as usize;
+            let (run, count) = if count > 128 {
+                (true, count - 128)
+            } else {
+                (false, count)
+            };
+            if count == 0 || x + count > width {
+                return Err(Error::new(ErrorKind::InvalidData, "bad Radiance RLE data"));
+            }
+
+            if run {
+                let mut value = [0_u8; 1];
+                reader.read_exact(&mut value)?;
+                for rgbe in &mut scanline[x..x + count] {
+                    rgbe[c] = value[0];
+                }
+            } else {
+                let mut values = vec![0_u8; count];
+                reader.read_exact(&mut values)?;
+                for (rgbe, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    rgbe[c] = value;
+                }
+            }
+            x += count;
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert a shared exponent RGBE value back to a linear colour.
+fn from_rgbe(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+    let scale = 2_f32.powi(rgbe[3] as i32 - 136);
+    [
+        (rgbe[0] as f32 + 0.5) * scale,
+        (rgbe[1] as f32 + 0.5) * scale,
+        (rgbe[2] as f32 + 0.5) * scale,
+    ]
+}
+
+/// Convert a linear colour to the shared exponent RGBE representation.
+fn to_rgbe(r: f32, g: f32, b: f32) -> [u8; 4] {
+    let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+    let max = r.max(g).max(b);
+    if max < 1e-32 || !max.is_finite() {
+        return [0; 4];
+    }
+
+    // max = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2_f32.powi(exponent);
+
+    [
+        (r * scale).min(255.0) as u8,
+        (g * scale).min(255.0) as u8,
+        (b * scale).min(255.0) as u8,
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+/// Run-length encode one channel of a scanline: runs of at least 4 equal bytes are stored as
+/// (128 + count, value), everything else as (count, values...).
+fn encode_channel(data: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4;
+
+    let mut x = 0;
+    while x < data.len() {
+        // Find the next run of at least MIN_RUN equal values
+        let mut run_start = x;
+        let mut run_length = 0;
+        while run_start < data.len() {
+            run_length = 1;
+            while run_length < 127
+                && run_start + run_length < data.len()
+                && data[run_start + run_length] == data[run_start]
+            {
+                run_length += 1;
+            }
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+
+        // Literals before the run
+        while x < run_start {
+            let count = (run_start - x).min(128);
+            out.push(count as u8);
+            out.extend_from_slice(&data[x..x + count]);
+            x += count;
+        }
+
+        if run_length >= MIN_RUN && run_start < data.len() {
+            out.push(128 + run_length as u8);
+            out.push(data[run_start]);
+            x = run_start + run_length;
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Greedy meshing of a `VoxelGrid` into quads.
+//!
+//! Faces between two set voxels are culled, across tile borders too. The remaining faces are
+//! grouped per plane and coplanar faces of the same material are merged into larger rectangles.
+
+use crate::prelude::*;
+use rayon::prelude::*;
+use theframework::prelude::FxHashMap;
+
+/// A merged, axis aligned face in global voxel coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct Quad {
+    /// The axis of the face normal (0 = x, 1 = y, 2 = z).
+    pub axis: usize,
+    /// True if the face points along the positive axis.
+    pub positive: bool,
+    /// The minimum corner of the face, the plane coordinate along the axis included.
+    pub origin: Vec3<i32>,
+    /// The extent along the two in-plane axes (`axis + 1` and `axis + 2`, wrapped).
+    pub size: Vec2<i32>,
+    pub material: u8,
+}
+
+impl Quad {
+    /// The outward facing unit normal.
+    pub fn normal(&self) -> Vec3<F> {
+        let mut n = Vec3::zero();
+        n[self.axis] = if self.positive { 1.0 } else { -1.0 };
+        n
+    }
+
+    /// The four corners, counter-clockwise when looking at the front of the face.
+    pub fn corners(&self) -> [Vec3<i32>; 4] {
+        let mut du = Vec3::zero();
+        let mut dv = Vec3::zero();
+        du[(self.axis + 1) % 3] = self.size.x;
+        dv[(self.axis + 2) % 3] = self.size.y;
+
+        let o = self.origin;
+        if self.positive {
+            [o, o + du, o + du + dv, o + dv]
+        } else {
+            [o, o + dv, o + du + dv, o + du]
+        }
+    }
+
+    /// The two triangles of the quad as corner indices, with the same winding as `corners`.
+    pub fn triangles() -> [[usize; 3]; 2] {
+        [[0, 1, 2], [0, 2, 3]]
+    }
+}
+
+/// A quad mesh of the visible surface of a grid.
+pub struct Mesh {
+    pub quads: Vec<Quad>,
+    /// The size of one voxel in output units.
+    pub scale: F,
+}
+
+/// Identifies a plane of faces: axis, direction and plane coordinate.
+type PlaneKey = (usize, bool, i32);
+
+/// The faces of one plane as in-plane coordinates and material.
+type PlaneFaces = Vec<(i32, i32, u8)>;
+
+impl Mesh {
+    /// Mesh the grid. By default one tile is one world unit (as in the editor), with
+    /// `voxel_units` one voxel is one world unit.
+    pub fn from_grid(grid: &VoxelGrid, voxel_units: bool) -> Self {
+        Self::build(grid, voxel_units, true)
+    }
+
+    /// Mesh only the shape of the grid, faces are merged regardless of their material and
+    /// all quads get material 0.
+    pub fn surface(grid: &VoxelGrid, voxel_units: bool) -> Self {
+        Self::build(grid, voxel_units, false)
+    }
+
+    fn build(grid: &VoxelGrid, voxel_units: bool, by_material: bool) -> Self {
+        let faces: Vec<(PlaneKey, i32, i32, u8)> = grid
+            .tiles
+            .par_iter()
+            .filter(|(_, tile)| tile.has_voxels)
+            .flat_map_iter(|(key, tile)| Self::visible_faces(grid, *key, tile, by_material))
+            .collect();
+
+        let mut planes: FxHashMap<PlaneKey, PlaneFaces> = FxHashMap::default();
+        for (plane, u, v, material) in faces {
+            planes.entry(plane).or_default().push((u, v, material));
+        }
+
+        let mut planes: Vec<(PlaneKey, PlaneFaces)> = planes.into_iter().collect();
+        planes.sort_by_key(|(key, _)| *key);
+
+        let quads = planes
+            .par_iter()
+            .flat_map_iter(|(plane, faces)| Self::merge_plane(*plane, faces))
+            .collect();
+
+        Self {
+            quads,
+            scale: if voxel_units {
+                1.0
+            } else {
+                1.0 / grid.density_f
+            },
+        }
+    }
+
+    /// Convert a corner in voxel coordinates to an output position.
+    #[inline]
+    pub fn position(&self, p: Vec3<i32>) -> Vec3<F> {
+        p.map(|v| v as F) * self.scale
+    }
+
+    /// The sorted list of the materials used by the mesh.
+    pub fn materials(&self) -> Vec<u8> {
+        let mut used = [false; 256];
+        for quad in &self.quads {
+            used[quad.material as usize] = true;
+        }
+        (0..=255_u8).filter(|m| used[*m as usize]).collect()
+    }
+
+    /// The number of triangles of the mesh.
+    pub fn triangle_count(&self) -> usize {
+        self.quads.len() * 2
+    }
+
+    /// Collect the faces of the tile which are not covered by a neighbouring voxel.
+    fn visible_faces(
+        grid: &VoxelGrid,
+        key: Coord,
+        tile: &Tile,
+        by_material: bool,
+    ) -> Vec<(PlaneKey, i32, i32, u8)> {
+        let d = tile.density as i32;
+        let origin = Vec3::new(key.0, key.1, key.2) * d;
+        let min = tile.bbox.min.map(|v| v as i32);
+        let max = tile.bbox.max.map(|v| v as i32);
+
+        let mut faces = vec![];
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let Some(material) = tile.get((x, y, z)) else {
+                        continue;
+                    };
+                    let local = Vec3::new(x, y, z);
+                    let global = origin + local;
+
+                    for axis in 0..3 {
+                        for positive in [true, false] {
+                            let mut n = local;
+                            n[axis] += if positive { 1 } else { -1 };
+
+                            let covered = if n[axis] >= 0 && n[axis] < d {
+                                tile.get((n.x, n.y, n.z)).is_some()
+                            } else {
+                                grid.get_voxel(origin + n).is_some()
+                            };
+
+                            if !covered {
+                                let plane = global[axis] + positive as i32;
+                                faces.push((
+                                    (axis, positive, plane),
+                                    global[(axis + 1) % 3],
+                                    global[(axis + 2) % 3],
+                                    if by_material { material } else { 0 },
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        faces
+    }
+
+    /// Greedily merge the faces of one plane into rectangles of the same material.
+    fn merge_plane(plane: PlaneKey, faces: &[(i32, i32, u8)]) -> Vec<Quad> {
+        let (axis, positive, coord) = plane;
+
+        let (mut min_u, mut min_v) = (i32::MAX, i32::MAX);
+        let (mut max_u, mut max_v) = (i32::MIN, i32::MIN);
+        for (u, v, _) in faces {
+            min_u = min_u.min(*u);
+            min_v = min_v.min(*v);
+            max_u = max_u.max(*u);
+            max_v = max_v.max(*v);
+        }
+
+        let width = (max_u - min_u + 1) as usize;
+        let height = (max_v - min_v + 1) as usize;
+        let mut mask: Vec<Option<u8>> = vec![None; width * height];
+        for (u, v, material) in faces {
+            mask[(*v - min_v) as usize * width + (*u - min_u) as usize] = Some(*material);
+        }
+
+        let mut quads = vec![];
+        for v in 0..height {
+            let mut u = 0;
+            while u < width {
+                let Some(material) = mask[v * width + u] else {
+                    u += 1;
+                    continue;
+                };
+
+                let mut w = 1;
+                while u + w < width && mask[v * width + u + w] == Some(material) {
+                    w += 1;
+                }
+
+                let mut h = 1;
+                while v + h < height
+                    && mask[(v + h) * width + u..(v + h) * width + u + w]
+                        .iter()
+                        .all(|m| *m == Some(material))
+                {
+                    h += 1;
+                }
+
+                for row in v..v + h {
+                    mask[row * width + u..row * width + u + w].fill(None);
+                }
+
+                let mut origin = Vec3::zero();
+                origin[axis] = coord;
+                origin[(axis + 1) % 3] = min_u + u as i32;
+                origin[(axis + 2) % 3] = min_v + v as i32;
+
+                quads.push(Quad {
+                    axis,
+                    positive,
+                    origin,
+                    size: Vec2::new(w as i32, h as i32),
+                    material,
+                });
+
+                u += w;
+            }
+        }
+        quads
+    }
+}
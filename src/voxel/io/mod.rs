@@ -0,0 +1,88 @@
+pub mod chunk;
+pub mod exr;
+pub mod gltf;
+pub mod hdr;
+pub mod mesh;
+pub mod obj;
+pub mod ply;
+pub mod pngimport;
+pub mod qubicle;
+pub mod stl;
+pub mod vox;
+
+use std::io::{Read, Result, Write};
+
+/// Read a single byte.
+pub fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+/// Read a little-endian u32.
+pub fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a little-endian i32.
+pub fn read_i32<R: Read>(reader: &mut R) -> Result<i32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+/// Read a little-endian f32.
+pub fn read_f32<R: Read>(reader: &mut R) -> Result<f32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+/// Read an unsigned LEB128 variable length integer.
+pub fn read_varint<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut value = 0_u64;
+    let mut shift = 0;
+    loop {
+        let byte = read_u8(reader)?;
+        if shift >= 64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "varint too long",
+            ));
+        }
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Write a little-endian u32.
+pub fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Write a little-endian i32.
+pub fn write_i32<W: Write>(writer: &mut W, value: i32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Write a little-endian f32.
+pub fn write_f32<W: Write>(writer: &mut W, value: f32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Write an unsigned LEB128 variable length integer.
+pub fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<()> {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
@@ -0,0 +1,112 @@
+//! Wavefront OBJ + MTL export of a greedy mesh, one material per used palette index.
+
+use super::mesh::{Mesh, Quad};
+use crate::prelude::*;
+use std::io::{Result, Write};
+use theframework::prelude::FxHashMap;
+
+/// Write the mesh as OBJ into `obj` and its materials into `mtl`. `mtl_name` is the file name
+/// of the material library as referenced from the OBJ file.
+pub fn export_obj<W: Write, M: Write>(
+    obj: &mut W,
+    mtl: &mut M,
+    mtl_name: &str,
+    mesh: &Mesh,
+    palette: &Palette,
+) -> Result<()> {
+    let mut quads: Vec<&Quad> = mesh.quads.iter().collect();
+    quads.sort_by_key(|quad| quad.material);
+
+    // Shared corner vertices
+    let mut indices: FxHashMap<(i32, i32, i32), usize> = FxHashMap::default();
+    let mut vertices = vec![];
+    let faces: Vec<[usize; 4]> = quads
+        .iter()
+        .map(|quad| {
+            quad.corners().map(|c| {
+                *indices.entry((c.x, c.y, c.z)).or_insert_with(|| {
+                    vertices.push(c);
+                    vertices.len()
+                })
+            })
+        })
+        .collect();
+
+    writeln!(obj, "# Shape-Z")?;
+    writeln!(obj, "mtllib {}", mtl_name)?;
+
+    for v in &vertices {
+        let p = mesh.position(*v);
+        writeln!(obj, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+
+    // Normals in the order +x, -x, +y, -y, +z, -z
+    for axis in 0..3 {
+        for sign in [1.0, -1.0] {
+            let mut n: Vec3<F> = Vec3::zero();
+            n[axis] = sign;
+            writeln!(obj, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+    }
+
+    let mut current = None;
+    for (quad, face) in quads.iter().zip(&faces) {
+        if current != Some(quad.material) {
+            current = Some(quad.material);
+            writeln!(obj, "usemtl {}", material_name(quad.material))?;
+        }
+
+        let n = quad.axis * 2 + if quad.positive { 1 } else { 2 };
+        for triangle in Quad::triangles() {
+            writeln!(
+                obj,
+                "f {}//{} {}//{} {}//{}",
+                face[triangle[0]], n, face[triangle[1]], n, face[triangle[2]], n
+            )?;
+        }
+    }
+
+    writeln!(mtl, "# Shape-Z")?;
+    for index in mesh.materials() {
+        write_material(mtl, index, &palette.materials[index as usize])?;
+    }
+
+    Ok(())
+}
+
+/// The name of the OBJ material of the given palette index.
+fn material_name(index: u8) -> String {
+    format!("palette_{}", index)
+}
+
+fn write_material<M: Write>(mtl: &mut M, index: u8, material: &Material) -> Result<()> {
+    let kd = material.base_color_linear();
+    let ks =
+        Vec3::broadcast(material.specular * 0.08).map2(kd, |s, c| s + (c - s) * material.metallic);
+    let ke = material.emission_color * material.emission_strength;
+    let ns = (1.0 - material.roughness).powi(2) * 1000.0;
+
+    writeln!(mtl)?;
+    writeln!(mtl, "newmtl {}", material_name(index))?;
+    writeln!(mtl, "Ka 0 0 0")?;
+    writeln!(mtl, "Kd {} {} {}", kd.x, kd.y, kd.z)?;
+    writeln!(mtl, "Ks {} {} {}", ks.x, ks.y, ks.z)?;
+    writeln!(mtl, "Ke {} {} {}", ke.x, ke.y, ke.z)?;
+    writeln!(mtl, "Ns {}", ns)?;
+    writeln!(mtl, "Ni {}", material.ior)?;
+    writeln!(mtl, "d {}", 1.0 - material.transmission)?;
+    writeln!(
+        mtl,
+        "illum {}",
+        if material.transmission > 0.0 { 4 } else { 2 }
+    )?;
+
+    // PBR extension, understood by Blender and most engines
+    writeln!(mtl, "Pr {}", material.roughness)?;
+    writeln!(mtl, "Pm {}", material.metallic)?;
+    writeln!(mtl, "Ps {}", material.sheen)?;
+    writeln!(mtl, "Pc {}", material.clearcoat)?;
+    writeln!(mtl, "Pcr {}", 1.0 - material.clearcoat_gloss)?;
+
+    Ok(())
+}
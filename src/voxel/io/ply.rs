@@ -0,0 +1,59 @@
+//! Binary little-endian PLY export of a greedy mesh with per-vertex colours.
+
+use super::mesh::{Mesh, Quad};
+use super::{write_f32, write_u32};
+use crate::prelude::*;
+use std::io::{Result, Write};
+
+/// Write the mesh as binary PLY. Every quad gets its own four vertices so that the colours
+/// and normals of neighbouring faces stay separate.
+pub fn export_ply<W: Write>(writer: &mut W, mesh: &Mesh, palette: &Palette) -> Result<()> {
+    write!(
+        writer,
+        "ply\n\
+         format binary_little_endian 1.0\n\
+         comment Shape-Z\n\
+         element vertex {}\n\
+         property float x\n\
+         property float y\n\
+         property float z\n\
+         property float nx\n\
+         property float ny\n\
+         property float nz\n\
+         property uchar red\n\
+         property uchar green\n\
+         property uchar blue\n\
+         element face {}\n\
+         property list uchar uint vertex_indices\n\
+         end_header\n",
+        mesh.quads.len() * 4,
+        mesh.triangle_count()
+    )?;
+
+    for quad in &mesh.quads {
+        let normal = quad.normal();
+        let color = palette.materials[quad.material as usize]
+            .base_color
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+
+        for corner in quad.corners() {
+            let p = mesh.position(corner);
+            for v in [p.x, p.y, p.z, normal.x, normal.y, normal.z] {
+                write_f32(writer, v)?;
+            }
+            writer.write_all(&[color.x, color.y, color.z])?;
+        }
+    }
+
+    for index in 0..mesh.quads.len() {
+        let base = index as u32 * 4;
+        for triangle in Quad::triangles() {
+            writer.write_all(&[3])?;
+            for corner in triangle {
+                write_u32(writer, base + corner as u32)?;
+            }
+        }
+    }
+
+    Ok(())
+}
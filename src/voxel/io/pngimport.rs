@@ -0,0 +1,161 @@
+//! PNG based importers: greyscale heightmaps and slice stacks.
+//!
+//! One pixel is one voxel. The images are centred on the origin in x / z and the voxels are
+//! placed from y = 0 upwards.
+
+use crate::prelude::*;
+use crate::voxel::color::luminance;
+use std::io::{Error, ErrorKind, Result};
+
+/// A decoded PNG with normalized RGBA values.
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<[F; 4]>,
+    has_alpha: bool,
+}
+
+/// Import a greyscale heightmap as terrain columns of up to `max_height` voxels.
+///
+/// `bands` is a list of (normalized upper height, material), sorted by height. A voxel gets
+/// the material of the first band its own height falls into, the last band covers the rest.
+pub fn import_heightmap(
+    data: &[u8],
+    grid: &mut VoxelGrid,
+    max_height: usize,
+    bands: &[(F, u8)],
+) -> Result<()> {
+    let image = decode(data)?;
+    let offset = (image.width as i32 / 2, image.height as i32 / 2);
+
+    for row in 0..image.height {
+        for column in 0..image.width {
+            let [r, g, b, _] = image.pixels[row * image.width + column];
+            let height = (luminance(Vec3::new(r, g, b)) * max_height as F).round() as usize;
+
+            for y in 0..height {
+                let h = (y as F + 0.5) / max_height as F;
+                let material = bands
+                    .iter()
+                    .find(|(upper, _)| h <= *upper)
+                    .or(bands.last())
+                    .map_or(0, |(_, m)| *m);
+
+                let v = Vec3::new(column as i32 - offset.0, y as i32, row as i32 - offset.1);
+                grid.set_create(to_world(grid, v), material);
+            }
+        }
+    }
+
+    grid.update_bboxes();
+    grid.update_bounds();
+    Ok(())
+}
+
+/// Height bands for terrain: water, sand, grass, rock and snow, each mapped onto the closest
+/// colour of the palette.
+pub fn default_bands(palette: &Palette) -> Vec<(F, u8)> {
+    [
+        (0.1, Vec3::new(0.2, 0.4, 0.8)),
+        (0.2, Vec3::new(0.85, 0.8, 0.55)),
+        (0.55, Vec3::new(0.3, 0.6, 0.2)),
+        (0.8, Vec3::new(0.5, 0.5, 0.5)),
+        (1.0, Vec3::new(0.95, 0.95, 0.95)),
+    ]
+    .iter()
+    .map(|(upper, color)| (*upper, palette.closest(*color).0))
+    .collect()
+}
+
+/// Import a stack of PNG slices, slice `i` becomes the voxel layer y = i. Pixel colours are
+/// mapped to the closest palette index. Transparent pixels are empty, for images without
+/// an alpha channel black pixels are empty.
+pub fn import_slices(slices: &[Vec<u8>], grid: &mut VoxelGrid, palette: &Palette) -> Result<()> {
+    let mut lookup: FxHashMap<[u8; 3], u8> = FxHashMap::default();
+
+    for (y, data) in slices.iter().enumerate() {
+        let image = decode(data)?;
+        let offset = (image.width as i32 / 2, image.height as i32 / 2);
+
+        for row in 0..image.height {
+            for column in 0..image.width {
+                let [r, g, b, a] = image.pixels[row * image.width + column];
+                let key = [r, g, b].map(|c| (c * 255.0).round() as u8);
+                let empty = if image.has_alpha {
+                    a < 0.5
+                } else {
+                    key == [0, 0, 0]
+                };
+                if empty {
+                    continue;
+                }
+
+                let material = *lookup
+                    .entry(key)
+                    .or_insert_with(|| palette.closest(Vec3::new(r, g, b)).0);
+
+                let v = Vec3::new(column as i32 - offset.0, y as i32, row as i32 - offset.1);
+                grid.set_create(to_world(grid, v), material);
+            }
+        }
+    }
+
+    grid.update_bboxes();
+    grid.update_bounds();
+    Ok(())
+}
+
+/// The world coordinate of the centre of the voxel.
+fn to_world(grid: &VoxelGrid, v: Vec3<i32>) -> Vec3<F> {
+    (v.map(|c| c as F) + 0.5) / grid.density_f
+}
+
+fn decode(data: &[u8]) -> Result<Image> {
+    let invalid = |err: png::DecodingError| Error::new(ErrorKind::InvalidData, err.to_string());
+
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(invalid)?;
+    let bytes = &buf[..info.buffer_size()];
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => {
+            return Err(Error::new(ErrorKind::InvalidData, "unexpanded PNG palette"));
+        }
+    };
+
+    // After expansion samples are either 8 or 16 bit
+    let wide = info.bit_depth == png::BitDepth::Sixteen;
+    let sample = |i: usize| -> F {
+        if wide {
+            u16::from_be_bytes([bytes[i * 2], bytes[i * 2 + 1]]) as F / 65535.0
+        } else {
+            bytes[i] as F / 255.0
+        }
+    };
+
+    let count = info.width as usize * info.height as usize;
+    let mut pixels = Vec::with_capacity(count);
+    for p in 0..count {
+        let s = |c: usize| sample(p * channels + c);
+        pixels.push(match channels {
+            1 => [s(0), s(0), s(0), 1.0],
+            2 => [s(0), s(0), s(0), s(1)],
+            3 => [s(0), s(1), s(2), 1.0],
+            _ => [s(0), s(1), s(2), s(3)],
+        });
+    }
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+        has_alpha: channels == 2 || channels == 4,
+    })
+}
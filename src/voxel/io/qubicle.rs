@@ -0,0 +1,446 @@
+//! Qubicle `.qb` and `.qbt` support.
+//!
+//! Qubicle is Y-up like the grid. Every matrix is placed at its position in global voxel
+//! coordinates, left-handed `.qb` files have their z axis mirrored. The true-colour voxels are
+//! quantized onto the palette, colours close to an existing entry reuse it.
+
+use super::{read_f32, read_i32, read_u8, read_u32, write_f32, write_i32, write_u32};
+use crate::prelude::*;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use theframework::prelude::FxHashMap;
+
+/// The maximum distance (in sRGB, per unit) at which an existing palette entry is reused.
+pub const COLOR_TOLERANCE: F = 0.02;
+
+const QB_VERSION: u32 = 0x0000_0101;
+const QB_CODEFLAG: u32 = 2;
+const QB_NEXTSLICEFLAG: u32 = 6;
+
+const QBT_NODE_MATRIX: u32 = 0;
+const QBT_NODE_MODEL: u32 = 1;
+const QBT_NODE_COMPOUND: u32 = 2;
+
+/// The largest matrix extent we accept per axis.
+const MAX_MATRIX_SIZE: u32 = 4096;
+
+/// The largest number of voxels we accept per matrix.
+const MAX_MATRIX_VOXELS: u64 = 64 * 1024 * 1024;
+
+/// The set voxels of all matrices of a file in global voxel coordinates.
+type Voxels = Vec<(Vec3<i32>, [u8; 3])>;
+
+/// Import a `.qb` file into the grid, quantizing its colours onto the palette.
+pub fn import_qb(data: &[u8], grid: &mut VoxelGrid, palette: &mut Palette) -> Result<()> {
+    let mut data = data;
+
+    let _version = read_u32(&mut data)?;
+    let bgra = read_u32(&mut data)? == 1;
+    let left_handed = read_u32(&mut data)? == 0;
+    let compressed = read_u32(&mut data)? != 0;
+    let _visibility_mask = read_u32(&mut data)?;
+    let count = read_u32(&mut data)?;
+
+    let mut voxels: Voxels = vec![];
+    for _ in 0..count {
+        let name_length = read_u8(&mut data)? as usize;
+        let mut name = vec![0; name_length];
+        data.read_exact(&mut name)?;
+
+        let size = read_size(&mut data)?;
+        let position = Vec3::new(
+            read_i32(&mut data)?,
+            read_i32(&mut data)?,
+            read_i32(&mut data)?,
+        );
+
+        let mut add = |index: usize, value: u32| {
+            let [r, g, b, a] = value.to_le_bytes();
+            if a == 0 {
+                return;
+            }
+            let local = Vec3::new(
+                index % size.x as usize,
+                (index / size.x as usize) % size.y as usize,
+                index / (size.x as usize * size.y as usize),
+            )
+            .map(|v| v as i32);
+
+            let mut p = position + local;
+            if left_handed {
+                p.z = -1 - p.z;
+            }
+            voxels.push((p, if bgra { [b, g, r] } else { [r, g, b] }));
+        };
+
+        let slice = size.x as usize * size.y as usize;
+        if compressed {
+            for z in 0..size.z as usize {
+                let mut index = 0;
+                loop {
+                    let value = read_u32(&mut data)?;
+                    if value == QB_NEXTSLICEFLAG {
+                        break;
+                    }
+                    let (run, value) = if value == QB_CODEFLAG {
+                        (read_u32(&mut data)? as usize, read_u32(&mut data)?)
+                    } else {
+                        (1, value)
+                    };
+                    if index + run > slice {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "qb run exceeds the matrix slice",
+                        ));
+                    }
+                    for i in index..index + run {
+                        add(z * slice + i, value);
+                    }
+                    index += run;
+                }
+            }
+        } else {
+            for index in 0..slice * size.z as usize {
+                add(index, read_u32(&mut data)?);
+            }
+        }
+    }
+
+    place_voxels(voxels, grid, palette);
+    Ok(())
+}
+
+/// Export the grid as a right-handed `.qb` file with a single RLE-compressed matrix.
+pub fn export_qb<W: Write>(writer: &mut W, grid: &VoxelGrid, palette: &Palette) -> Result<()> {
+    write_u32(writer, QB_VERSION)?;
+    write_u32(writer, 0)?; // RGBA
+    write_u32(writer, 1)?; // Right handed
+    write_u32(writer, 1)?; // Compressed
+    write_u32(writer, 0)?; // No visibility mask
+    let Some((min, max)) = voxel_bounds(grid) else {
+        return write_u32(writer, 0);
+    };
+    write_u32(writer, 1)?;
+
+    let name = b"Shape-Z";
+    writer.write_all(&[name.len() as u8])?;
+    writer.write_all(name)?;
+
+    let size = max - min + Vec3::one();
+    for v in [size.x, size.y, size.z, min.x, min.y, min.z] {
+        write_i32(writer, v)?;
+    }
+
+    let color = |v: Vec3<i32>| -> u32 {
+        grid.get_voxel(v).map_or(0, |m| {
+            let [r, g, b] = srgb_bytes(palette, m);
+            u32::from_le_bytes([r, g, b, 255])
+        })
+    };
+
+    let write_run = |writer: &mut W, run: u32, value: u32| -> Result<()> {
+        if run > 2 {
+            write_u32(writer, QB_CODEFLAG)?;
+            write_u32(writer, run)?;
+            write_u32(writer, value)
+        } else {
+            for _ in 0..run {
+                write_u32(writer, value)?;
+            }
+            Ok(())
+        }
+    };
+
+    for z in min.z..=max.z {
+        let mut run = 0;
+        let mut current = 0;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let value = color(Vec3::new(x, y, z));
+                if run > 0 && value != current {
+                    write_run(writer, run, current)?;
+                    run = 0;
+                }
+                current = value;
+                run += 1;
+            }
+        }
+        write_run(writer, run, current)?;
+        write_u32(writer, QB_NEXTSLICEFLAG)?;
+    }
+
+    Ok(())
+}
+
+/// Import a `.qbt` file into the grid, quantizing its colours onto the palette.
+pub fn import_qbt(data: &[u8], grid: &mut VoxelGrid, palette: &mut Palette) -> Result<()> {
+    let mut data = data;
+
+    let mut magic = [0; 4];
+    data.read_exact(&mut magic)?;
+    if &magic != b"QB 2" {
+        return Err(Error::new(ErrorKind::InvalidData, "not a Qubicle QBT file"));
+    }
+    let _major = read_u8(&mut data)?;
+    let _minor = read_u8(&mut data)?;
+    for _ in 0..3 {
+        let _scale = read_f32(&mut data)?;
+    }
+
+    expect_section(&mut data, b"COLORMAP")?;
+    let count = read_u32(&mut data)? as usize;
+    let mut colormap = Vec::with_capacity(count.min(256));
+    for _ in 0..count {
+        let mut c = [0; 4];
+        data.read_exact(&mut c)?;
+        colormap.push([c[0], c[1], c[2]]);
+    }
+
+    expect_section(&mut data, b"DATATREE")?;
+    let mut voxels: Voxels = vec![];
+    read_qbt_node(&mut data, &colormap, &mut voxels, 0)?;
+
+    place_voxels(voxels, grid, palette);
+    Ok(())
+}
+
+/// Export the grid as a `.qbt` file: the palette is written as the color map and the grid
+/// as a single matrix inside a model node.
+pub fn export_qbt<W: Write>(writer: &mut W, grid: &VoxelGrid, palette: &Palette) -> Result<()> {
+    writer.write_all(b"QB 2")?;
+    writer.write_all(&[1, 0])?;
+    for _ in 0..3 {
+        write_f32(writer, 1.0)?;
+    }
+
+    writer.write_all(b"COLORMAP")?;
+    write_u32(writer, 256)?;
+    for index in 0..=255_u8 {
+        let [r, g, b] = srgb_bytes(palette, index);
+        writer.write_all(&[r, g, b, 255])?;
+    }
+
+    writer.write_all(b"DATATREE")?;
+
+    let mut children = vec![];
+    let mut child_count = 0;
+    if let Some((min, max)) = voxel_bounds(grid) {
+        let size = max - min + Vec3::one();
+
+        // Voxel data is stored x, z, y (y innermost): colour map index, 2 unused bytes, mask
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        for x in min.x..=max.x {
+            for z in min.z..=max.z {
+                for y in min.y..=max.y {
+                    let voxel = match grid.get_voxel(Vec3::new(x, y, z)) {
+                        Some(index) => [index, 0, 0, 1],
+                        None => [0; 4],
+                    };
+                    encoder.write_all(&voxel)?;
+                }
+            }
+        }
+        let compressed = encoder.finish()?;
+
+        let name = b"Shape-Z";
+        let mut matrix = vec![];
+        write_u32(&mut matrix, name.len() as u32)?;
+        matrix.write_all(name)?;
+        for v in [min.x, min.y, min.z, 1, 1, 1] {
+            write_i32(&mut matrix, v)?;
+        }
+        for _ in 0..3 {
+            write_f32(&mut matrix, 0.0)?;
+        }
+        for v in [size.x, size.y, size.z] {
+            write_i32(&mut matrix, v)?;
+        }
+        write_u32(&mut matrix, compressed.len() as u32)?;
+        matrix.write_all(&compressed)?;
+
+        write_u32(&mut children, QBT_NODE_MATRIX)?;
+        write_u32(&mut children, matrix.len() as u32)?;
+        children.write_all(&matrix)?;
+        child_count = 1;
+    }
+
+    write_u32(writer, QBT_NODE_MODEL)?;
+    write_u32(writer, 4 + children.len() as u32)?;
+    write_u32(writer, child_count)?;
+    writer.write_all(&children)
+}
+
+/// Read a node of the QBT data tree and collect the voxels of all matrices in it.
+fn read_qbt_node(
+    data: &mut &[u8],
+    colormap: &[[u8; 3]],
+    voxels: &mut Voxels,
+    depth: usize,
+) -> Result<()> {
+    if depth > 64 {
+        return Err(Error::new(ErrorKind::InvalidData, "qbt tree too deep"));
+    }
+
+    let node_type = read_u32(data)?;
+    let size = read_u32(data)? as usize;
+    if data.len() < size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "truncated qbt node"));
+    }
+    let (mut content, rest) = data.split_at(size);
+    *data = rest;
+
+    match node_type {
+        QBT_NODE_MATRIX => read_qbt_matrix(&mut content, colormap, voxels),
+        QBT_NODE_MODEL => {
+            for _ in 0..read_u32(&mut content)? {
+                read_qbt_node(&mut content, colormap, voxels, depth + 1)?;
+            }
+            Ok(())
+        }
+        QBT_NODE_COMPOUND => {
+            read_qbt_matrix(&mut content, colormap, voxels)?;
+            for _ in 0..read_u32(&mut content)? {
+                read_qbt_node(&mut content, colormap, voxels, depth + 1)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn read_qbt_matrix(data: &mut &[u8], colormap: &[[u8; 3]], voxels: &mut Voxels) -> Result<()> {
+    let name_length = read_u32(data)? as usize;
+    if data.len() < name_length {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "truncated qbt matrix"));
+    }
+    *data = &data[name_length..];
+
+    let position = Vec3::new(read_i32(data)?, read_i32(data)?, read_i32(data)?);
+    for _ in 0..3 {
+        let _local_scale = read_u32(data)?;
+    }
+    for _ in 0..3 {
+        let _pivot = read_f32(data)?;
+    }
+    let size = read_size(data)?;
+
+    let compressed_size = read_u32(data)? as usize;
+    if data.len() < compressed_size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "truncated qbt matrix"));
+    }
+    let (compressed, rest) = data.split_at(compressed_size);
+    *data = rest;
+
+    let count = size.x as usize * size.y as usize * size.z as usize;
+    let mut decoded = vec![];
+    ZlibDecoder::new(compressed)
+        .take(count as u64 * 4)
+        .read_to_end(&mut decoded)?;
+    if decoded.len() != count * 4 {
+        return Err(Error::new(ErrorKind::InvalidData, "incomplete qbt matrix"));
+    }
+
+    let mut index = 0;
+    for x in 0..size.x {
+        for z in 0..size.z {
+            for y in 0..size.y {
+                let v = &decoded[index * 4..index * 4 + 4];
+                index += 1;
+                if v[3] == 0 {
+                    continue;
+                }
+                let color = if colormap.is_empty() {
+                    [v[0], v[1], v[2]]
+                } else {
+                    colormap.get(v[0] as usize).copied().unwrap_or([255; 3])
+                };
+                voxels.push((position + Vec3::new(x, y, z), color));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Quantize the colours onto the palette and set the voxels in the grid.
+fn place_voxels(voxels: Voxels, grid: &mut VoxelGrid, palette: &mut Palette) {
+    let mut counts: FxHashMap<[u8; 3], usize> = FxHashMap::default();
+    for (_, color) in &voxels {
+        *counts.entry(*color).or_default() += 1;
+    }
+    let mut colors: Vec<([u8; 3], usize)> = counts.into_iter().collect();
+    colors.sort();
+
+    // Entries already used in the grid must keep their colour
+    let mut used = [false; 256];
+    grid.for_each_voxel(|_, m| used[m as usize] = true);
+
+    let indices = palette.quantize(
+        &colors
+            .iter()
+            .map(|(c, n)| (Vec3::<u8>::from(*c).map(|v| v as F / 255.0), *n))
+            .collect::<Vec<_>>(),
+        &used,
+        COLOR_TOLERANCE,
+    );
+    let lookup: FxHashMap<[u8; 3], u8> = colors
+        .iter()
+        .zip(indices)
+        .map(|((c, _), index)| (*c, index))
+        .collect();
+
+    for (p, color) in voxels {
+        grid.set_voxel_create(p, lookup[&color]);
+    }
+
+    grid.update_bboxes();
+    grid.update_bounds();
+}
+
+/// The inclusive bounds of all set voxels in global voxel coordinates.
+fn voxel_bounds(grid: &VoxelGrid) -> Option<(Vec3<i32>, Vec3<i32>)> {
+    let mut bounds: Option<(Vec3<i32>, Vec3<i32>)> = None;
+    grid.for_each_voxel(|v, _| {
+        bounds = Some(match bounds {
+            Some((min, max)) => (Vec3::partial_min(min, v), Vec3::partial_max(max, v)),
+            None => (v, v),
+        });
+    });
+    bounds
+}
+
+fn srgb_bytes(palette: &Palette, index: u8) -> [u8; 3] {
+    let c = palette.materials[index as usize].base_color;
+    [c.x, c.y, c.z].map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Read the extent of a matrix, the product of the axes fits into `MAX_MATRIX_VOXELS`.
+fn read_size(data: &mut &[u8]) -> Result<Vec3<i32>> {
+    let size = [read_u32(data)?, read_u32(data)?, read_u32(data)?];
+    let count = size
+        .iter()
+        .try_fold(1_u64, |count, s| count.checked_mul(*s as u64));
+    if size.iter().any(|s| *s == 0 || *s > MAX_MATRIX_SIZE)
+        || count.is_none_or(|count| count > MAX_MATRIX_VOXELS)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid matrix size {:?}", size),
+        ));
+    }
+    Ok(Vec3::<u32>::from(size).map(|s| s as i32))
+}
+
+fn expect_section(data: &mut &[u8], name: &[u8; 8]) -> Result<()> {
+    let mut section = [0; 8];
+    data.read_exact(&mut section)?;
+    if &section != name {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("missing {} section", String::from_utf8_lossy(name)),
+        ));
+    }
+    Ok(())
+}
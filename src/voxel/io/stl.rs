@@ -0,0 +1,290 @@
+//! Watertight STL export for 3D printing.
+//!
+//! The surface is greedily meshed regardless of materials. To keep the result watertight, every
+//! quad edge is split at all quad corners lying on it (no T-junctions) and such quads are
+//! triangulated as a fan around their centre. Voxels which only touch along an edge or in a
+//! corner would produce non-manifold geometry, the vertices there are duplicated per solid
+//! (or empty) sector and moved apart by a tiny amount.
+//!
+//! STL is Z-up, the grid is Y-up: grid (x, y, z) is written as (x, -z, y).
+
+use super::mesh::{Mesh, Quad};
+use super::{write_f32, write_u32};
+use crate::prelude::*;
+use std::io::{Error, ErrorKind, Result, Write};
+use theframework::prelude::FxHashMap;
+
+/// The default physical size of one voxel in millimetres.
+pub const DEFAULT_VOXEL_SIZE_MM: F = 1.0;
+
+/// How far duplicated vertices at non-manifold edges and corners are moved apart, in voxels.
+const SPLIT_EPSILON: F = 0.01;
+
+type Triangle = [Vec3<F>; 3];
+
+/// Write the grid as STL, binary or ASCII. `voxel_size_mm` is the physical size of a voxel.
+/// An empty grid is an error, it has no solid to print.
+pub fn export_stl<W: Write>(
+    writer: &mut W,
+    grid: &VoxelGrid,
+    voxel_size_mm: F,
+    ascii: bool,
+) -> Result<()> {
+    let mut mesh = Mesh::surface(grid, true);
+    if mesh.quads.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the grid has no voxels",
+        ));
+    }
+    mesh.scale = voxel_size_mm;
+
+    let triangles = triangulate(grid, &mesh);
+
+    if ascii {
+        writeln!(writer, "solid shapez")?;
+        for triangle in &triangles {
+            let n = facet_normal(triangle);
+            writeln!(writer, "  facet normal {} {} {}", n.x, n.y, n.z)?;
+            writeln!(writer, "    outer loop")?;
+            for v in triangle {
+                writeln!(writer, "      vertex {} {} {}", v.x, v.y, v.z)?;
+            }
+            writeln!(writer, "    endloop")?;
+            writeln!(writer, "  endfacet")?;
+        }
+        writeln!(writer, "endsolid shapez")?;
+    } else {
+        let mut header = [0_u8; 80];
+        let name = b"Shape-Z binary STL";
+        header[..name.len()].copy_from_slice(name);
+        writer.write_all(&header)?;
+        write_u32(writer, triangles.len() as u32)?;
+        for triangle in &triangles {
+            let n = facet_normal(triangle);
+            for v in [n].iter().chain(triangle) {
+                write_f32(writer, v.x)?;
+                write_f32(writer, v.y)?;
+                write_f32(writer, v.z)?;
+            }
+            writer.write_all(&[0, 0])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Triangulate the quads without T-junctions, in STL coordinates.
+fn triangulate(grid: &VoxelGrid, mesh: &Mesh) -> Vec<Triangle> {
+    // For every axis aligned line through quad corners, the sorted corner coordinates on it
+    let mut lines: FxHashMap<(usize, i32, i32), Vec<i32>> = FxHashMap::default();
+    for quad in &mesh.quads {
+        for c in quad.corners() {
+            for axis in 0..3 {
+                lines
+                    .entry((axis, c[(axis + 1) % 3], c[(axis + 2) % 3]))
+                    .or_default()
+                    .push(c[axis]);
+            }
+        }
+    }
+    for line in lines.values_mut() {
+        line.sort_unstable();
+        line.dedup();
+    }
+
+    let to_stl = |p: Vec3<F>| Vec3::new(p.x, -p.z, p.y) * mesh.scale;
+
+    let mut triangles = vec![];
+    for quad in &mesh.quads {
+        let corners = quad.corners();
+
+        let mut outline = vec![];
+        for i in 0..4 {
+            let (from, to) = (corners[i], corners[(i + 1) % 4]);
+            outline.push(from);
+
+            let axis = (0..3).find(|a| from[*a] != to[*a]).unwrap_or(0);
+            let line = &lines[&(axis, from[(axis + 1) % 3], from[(axis + 2) % 3])];
+            let (lo, hi) = (from[axis].min(to[axis]), from[axis].max(to[axis]));
+            let start = line.partition_point(|v| *v <= lo);
+            let end = line.partition_point(|v| *v < hi);
+
+            let mut between: Vec<Vec3<i32>> = line[start..end]
+                .iter()
+                .map(|v| {
+                    let mut p = from;
+                    p[axis] = *v;
+                    p
+                })
+                .collect();
+            if from[axis] > to[axis] {
+                between.reverse();
+            }
+            outline.extend(between);
+        }
+
+        let points: Vec<Vec3<F>> = outline
+            .iter()
+            .map(|p| to_stl(p.map(|v| v as F) + vertex_offset(grid, quad, *p)))
+            .collect();
+
+        if points.len() == 4 {
+            for t in Quad::triangles() {
+                triangles.push(t.map(|i| points[i]));
+            }
+        } else {
+            let o = corners[0].map(|v| v as F);
+            let size = (corners[2] - corners[0]).map(|v| v as F);
+            let center = to_stl(o + size * 0.5);
+            for i in 0..points.len() {
+                triangles.push([center, points[i], points[(i + 1) % points.len()]]);
+            }
+        }
+    }
+
+    triangles
+}
+
+/// The offset of the quad vertex at lattice point `p`. Zero unless the vertex is non-manifold,
+/// then it moves into the sector of the 2x2x2 cells around `p` the quad belongs to.
+fn vertex_offset(grid: &VoxelGrid, quad: &Quad, p: Vec3<i32>) -> Vec3<F> {
+    // Cells around p are indexed by bits (x, y, z), bit set = the cell at offset 0, else -1
+    let cell = |bits: usize| -> Vec3<i32> {
+        p + Vec3::new(bits & 1, (bits >> 1) & 1, (bits >> 2) & 1).map(|b| b as i32 - 1)
+    };
+
+    let mut solid = 0_u8;
+    for bits in 0..8 {
+        if grid.get_voxel(cell(bits)).is_some() {
+            solid |= 1 << bits;
+        }
+    }
+
+    let solid_sectors = sectors(solid);
+    let empty_sectors = sectors(!solid);
+    if solid_sectors.len() < 2 && empty_sectors.len() < 2 {
+        return Vec3::zero();
+    }
+
+    // The solid and the empty cell next to p covered by the quad
+    let center = quad.corners()[0] * 2 + (quad.corners()[2] - quad.corners()[0]);
+    let mut solid_bits = 0;
+    for axis in 0..3 {
+        let bit = if axis == quad.axis {
+            !quad.positive as usize
+        } else {
+            (center[axis] >= p[axis] * 2) as usize
+        };
+        solid_bits |= bit << axis;
+    }
+    let empty_bits = solid_bits ^ (1 << quad.axis);
+
+    let direction = |mask: u8| -> Vec3<F> {
+        (0..8)
+            .filter(|bits| mask & (1 << bits) != 0)
+            .map(|bits| cell(bits).map2(p, |c, p| (c - p) as F + 0.5))
+            .sum()
+    };
+
+    let dir = if solid_sectors.len() > 1 {
+        solid_sectors
+            .iter()
+            .find(|s| *s & (1 << solid_bits) != 0)
+            .map(|s| direction(*s))
+    } else {
+        empty_sectors
+            .iter()
+            .find(|s| *s & (1 << empty_bits) != 0)
+            .map(|s| -direction(*s))
+    };
+
+    match dir {
+        Some(dir) if dir.magnitude_squared() > 0.0 => dir.normalized() * SPLIT_EPSILON,
+        _ => Vec3::zero(),
+    }
+}
+
+/// Split the set cells of a 2x2x2 mask into face connected sectors.
+fn sectors(mask: u8) -> Vec<u8> {
+    let mut sectors = vec![];
+    let mut remaining = mask;
+    while remaining != 0 {
+        let mut sector = 1_u8 << remaining.trailing_zeros();
+        loop {
+            let mut grown = sector;
+            for bits in 0..8 {
+                if sector & (1 << bits) != 0 {
+                    for axis in 0..3 {
+                        grown |= (1 << (bits ^ (1 << axis))) & remaining;
+                    }
+                }
+            }
+            if grown == sector {
+                break;
+            }
+            sector = grown;
+        }
+        remaining &= !sector;
+        sectors.push(sector);
+    }
+    sectors
+}
+
+fn facet_normal(triangle: &Triangle) -> Vec3<F> {
+    let n = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+    if n.magnitude_squared() > 0.0 {
+        n.normalized()
+    } else {
+        Vec3::zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every edge of the exported triangles is shared by exactly two triangles, once in each
+    /// direction.
+    fn assert_edge_manifold(voxels: &[Vec3<i32>]) {
+        let mut grid = VoxelGrid::empty([0.0; 3], 4);
+        for voxel in voxels {
+            grid.set_voxel_create(*voxel, 1);
+        }
+        grid.update_bboxes();
+
+        let mut mesh = Mesh::surface(&grid, true);
+        mesh.scale = DEFAULT_VOXEL_SIZE_MM;
+        let triangles = triangulate(&grid, &mesh);
+        assert!(!triangles.is_empty());
+
+        let mut edges: FxHashMap<([u32; 3], [u32; 3]), usize> = FxHashMap::default();
+        for triangle in &triangles {
+            for i in 0..3 {
+                let from = triangle[i].map(F::to_bits).into_array();
+                let to = triangle[(i + 1) % 3].map(F::to_bits).into_array();
+                *edges.entry((from, to)).or_default() += 1;
+            }
+        }
+        for ((from, to), count) in &edges {
+            assert_eq!(*count, 1, "edge {from:?} {to:?} is used twice");
+            assert_eq!(
+                edges.get(&(*to, *from)),
+                Some(&1),
+                "edge {from:?} {to:?} is open"
+            );
+        }
+    }
+
+    #[test]
+    fn edge_touching_voxels_are_manifold() {
+        assert_edge_manifold(&[Vec3::new(0, 0, 0), Vec3::new(1, 1, 0)]);
+        // Across a tile border
+        assert_edge_manifold(&[Vec3::new(3, 0, 0), Vec3::new(4, 1, 0)]);
+    }
+
+    #[test]
+    fn corner_touching_voxels_are_manifold() {
+        assert_edge_manifold(&[Vec3::new(0, 0, 0), Vec3::new(1, 1, 1)]);
+    }
+}
@@ -0,0 +1,656 @@
+//! MagicaVoxel `.vox` support.
+//!
+//! MagicaVoxel is Z-up, the grid is Y-up. A voxel at `(x, y, z)` in the file ends up at
+//! `(x, z, -1 - y)` in the grid, which keeps the handedness. Colour index `i` of the file maps to
+//! palette index `i` (index 0 means empty in `.vox`).
+
+use super::{read_i32, write_i32};
+use crate::prelude::*;
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// A chunk of a `.vox` file.
+struct Chunk<'a> {
+    id: [u8; 4],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+/// A model from a SIZE / XYZI chunk pair.
+struct Model {
+    size: [i32; 3],
+    voxels: Vec<[u8; 4]>,
+}
+
+/// A node of the scene graph (nTRN, nGRP, nSHP).
+enum Node {
+    Transform {
+        child: i32,
+        rotation: Matrix,
+        translation: [i32; 3],
+    },
+    Group {
+        children: Vec<i32>,
+    },
+    Shape {
+        models: Vec<i32>,
+    },
+}
+
+/// An integer rotation matrix of the scene graph.
+type Matrix = [[i32; 3]; 3];
+
+/// A model placed in the scene: (model index, rotation, translation).
+type Instance = (usize, Matrix, [i32; 3]);
+
+const IDENTITY: Matrix = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+/// Import a `.vox` file into the grid and palette.
+///
+/// Every voxel of the file becomes one voxel of the grid, models larger than a tile are
+/// spread over as many tiles as needed. The RGBA chunk (or the MagicaVoxel default palette)
+/// and the MATL chunks are mapped onto the palette materials.
+pub fn import_vox(data: &[u8], grid: &mut VoxelGrid, palette: &mut Palette) -> Result<()> {
+    let mut data = data;
+
+    let mut magic = [0; 4];
+    read_exact(&mut data, &mut magic)?;
+    if &magic != b"VOX " {
+        return Err(Error::new(ErrorKind::InvalidData, "not a MagicaVoxel file"));
+    }
+    let _version = read_i32(&mut data)?;
+
+    let main = read_chunk(&mut data)?;
+    if &main.id != b"MAIN" {
+        return Err(Error::new(ErrorKind::InvalidData, "missing MAIN chunk"));
+    }
+
+    let mut models: Vec<Model> = vec![];
+    let mut size = None;
+    let mut colors = default_palette();
+    let mut materials: FxHashMap<i32, FxHashMap<String, String>> = FxHashMap::default();
+    let mut nodes: FxHashMap<i32, Node> = FxHashMap::default();
+
+    let mut children = main.children;
+    while !children.is_empty() {
+        let chunk = read_chunk(&mut children)?;
+        let mut content = chunk.content;
+
+        match &chunk.id {
+            b"SIZE" => {
+                size = Some([
+                    read_i32(&mut content)?,
+                    read_i32(&mut content)?,
+                    read_i32(&mut content)?,
+                ]);
+            }
+            b"XYZI" => {
+                let size = size
+                    .take()
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "XYZI without SIZE"))?;
+                let count = read_i32(&mut content)?.max(0) as usize;
+                if count.saturating_mul(4) > content.len() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "invalid XYZI voxel count",
+                    ));
+                }
+                let mut voxels = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let mut v = [0; 4];
+                    read_exact(&mut content, &mut v)?;
+                    voxels.push(v);
+                }
+                models.push(Model { size, voxels });
+            }
+            b"RGBA" => {
+                // Entry i of the chunk is the colour of index i + 1
+                for i in 0..255 {
+                    let mut c = [0; 4];
+                    read_exact(&mut content, &mut c)?;
+                    colors[i + 1] = c;
+                }
+            }
+            b"MATL" => {
+                let id = read_i32(&mut content)?;
+                materials.insert(id, read_dict(&mut content)?);
+            }
+            b"nTRN" => {
+                let id = read_i32(&mut content)?;
+                let _attributes = read_dict(&mut content)?;
+                let child = read_i32(&mut content)?;
+                let _reserved = read_i32(&mut content)?;
+                let _layer = read_i32(&mut content)?;
+                let frames = read_i32(&mut content)?;
+
+                let mut rotation = IDENTITY;
+                let mut translation = [0; 3];
+                if frames > 0 {
+                    let frame = read_dict(&mut content)?;
+                    if let Some(r) = frame.get("_r")
+                        && let Ok(r) = r.parse::<u8>()
+                    {
+                        rotation = decode_rotation(r);
+                    }
+                    if let Some(t) = frame.get("_t") {
+                        for (i, v) in t.split_whitespace().take(3).enumerate() {
+                            translation[i] = v.parse().unwrap_or(0);
+                        }
+                    }
+                }
+
+                nodes.insert(
+                    id,
+                    Node::Transform {
+                        child,
+                        rotation,
+                        translation,
+                    },
+                );
+            }
+            b"nGRP" => {
+                let id = read_i32(&mut content)?;
+                let _attributes = read_dict(&mut content)?;
+                let count = read_i32(&mut content)?.max(0);
+                let mut children = vec![];
+                for _ in 0..count {
+                    children.push(read_i32(&mut content)?);
+                }
+                nodes.insert(id, Node::Group { children });
+            }
+            b"nSHP" => {
+                let id = read_i32(&mut content)?;
+                let _attributes = read_dict(&mut content)?;
+                let count = read_i32(&mut content)?.max(0);
+                let mut models = vec![];
+                for _ in 0..count {
+                    models.push(read_i32(&mut content)?);
+                    let _attributes = read_dict(&mut content)?;
+                }
+                nodes.insert(id, Node::Shape { models });
+            }
+            _ => {}
+        }
+    }
+
+    // Place the models, either through the scene graph or all at the origin
+    let mut instances: Vec<Instance> = vec![];
+    if nodes.contains_key(&0) {
+        collect_instances(&nodes, 0, IDENTITY, [0; 3], &mut instances, 0);
+    } else {
+        for index in 0..models.len() {
+            instances.push((index, IDENTITY, [0; 3]));
+        }
+    }
+
+    let step = 1.0 / grid.density_f;
+    for (index, rotation, translation) in instances {
+        let Some(model) = models.get(index) else {
+            continue;
+        };
+        let pivot = model.size.map(|s| s / 2);
+
+        for v in &model.voxels {
+            let local = [
+                v[0] as i32 - pivot[0],
+                v[1] as i32 - pivot[1],
+                v[2] as i32 - pivot[2],
+            ];
+            let p = transform(&rotation, translation, local);
+
+            // Z-up to Y-up
+            let wc = Vec3::new(p[0] as F, p[2] as F, (-1 - p[1]) as F) + Vec3::broadcast(0.5);
+            grid.set_create(wc * step, v[3]);
+        }
+    }
+
+    grid.update_bboxes();
+    grid.update_bounds();
+
+    // Palette
+    for (index, c) in colors.iter().enumerate().skip(1) {
+        let mut material = Material::default();
+        let color = Vec3::new(c[0] as F, c[1] as F, c[2] as F) / 255.0;
+
+        if let Some(props) = materials.get(&(index as i32)) {
+            apply_matl(&mut material, color, props);
+        }
+
+        palette.materials[index] = material;
+        palette.set_base_color(index as u8, color);
+    }
+
+    Ok(())
+}
+
+/// The maximum size of a single model per axis.
+const MAX_MODEL_SIZE: i32 = 256;
+
+/// Export the grid as a `.vox` file. An empty grid is an error, the file needs a model.
+///
+/// Content larger than 256 voxels per axis is split into several models which are placed
+/// through a nTRN / nGRP scene graph. Base colours are written to the RGBA chunk and the
+/// PBR parameters of the materials to MATL chunks.
+pub fn export_vox<W: Write>(writer: &mut W, grid: &VoxelGrid, palette: &Palette) -> Result<()> {
+    // Collect the voxels in MagicaVoxel coordinates
+    let mut voxels: Vec<(Vec3<i32>, u8)> = vec![];
+    let mut used = [false; 256];
+    grid.for_each_voxel(|v, mat| {
+        used[mat as usize] = true;
+        voxels.push((Vec3::new(v.x, -1 - v.z, v.y), mat));
+    });
+    if voxels.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the grid has no voxels",
+        ));
+    }
+
+    // Index 0 means empty in .vox, move material 0 to a free index. If all indices are used
+    // it is merged into the used material with the closest base colour.
+    let mut color_index: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut source_material: [u8; 256] = std::array::from_fn(|i| i as u8);
+    if used[0] {
+        if let Some(free) = (1..256).rev().find(|i| !used[*i]) {
+            color_index[0] = free as u8;
+            source_material[free] = 0;
+        } else {
+            let color = palette.get(0).base_color;
+            color_index[0] = (1..=255)
+                .min_by(|a, b| {
+                    let distance = |i: u8| (palette.get(i).base_color - color).magnitude_squared();
+                    distance(*a).total_cmp(&distance(*b))
+                })
+                .unwrap_or(255);
+        }
+    }
+
+    let min = voxels
+        .iter()
+        .fold(Vec3::broadcast(i32::MAX), |acc, (v, _)| {
+            Vec3::partial_min(acc, *v)
+        });
+    let max = voxels
+        .iter()
+        .fold(Vec3::broadcast(i32::MIN), |acc, (v, _)| {
+            Vec3::partial_max(acc, *v)
+        });
+
+    // Split into models of at most 256^3
+    let mut models: FxHashMap<(i32, i32, i32), Vec<[u8; 4]>> = FxHashMap::default();
+    for (v, mat) in &voxels {
+        let offset = *v - min;
+        let block = offset.map(|o| o / MAX_MODEL_SIZE);
+        let local = offset - block * MAX_MODEL_SIZE;
+        models
+            .entry((block.x, block.y, block.z))
+            .or_default()
+            .push([
+                local.x as u8,
+                local.y as u8,
+                local.z as u8,
+                color_index[*mat as usize],
+            ]);
+    }
+    let mut blocks: Vec<(i32, i32, i32)> = models.keys().copied().collect();
+    blocks.sort();
+
+    let mut children = vec![];
+
+    for block in &blocks {
+        let block_v = Vec3::new(block.0, block.1, block.2);
+        let size = (max - min - block_v * MAX_MODEL_SIZE + 1).map(|s| s.min(MAX_MODEL_SIZE));
+        let model = &models[block];
+
+        let mut content = vec![];
+        for s in size {
+            write_i32(&mut content, s)?;
+        }
+        write_chunk(&mut children, b"SIZE", &content)?;
+
+        let mut content = vec![];
+        write_i32(&mut content, model.len() as i32)?;
+        for v in model {
+            content.extend_from_slice(v);
+        }
+        write_chunk(&mut children, b"XYZI", &content)?;
+    }
+
+    // Scene graph: root transform -> group -> (transform -> shape) per model
+    let mut content = vec![];
+    write_i32(&mut content, 0)?;
+    write_dict(&mut content, &[])?;
+    write_i32(&mut content, 1)?;
+    write_i32(&mut content, -1)?;
+    write_i32(&mut content, -1)?;
+    write_i32(&mut content, 1)?;
+    write_dict(&mut content, &[])?;
+    write_chunk(&mut children, b"nTRN", &content)?;
+
+    let mut content = vec![];
+    write_i32(&mut content, 1)?;
+    write_dict(&mut content, &[])?;
+    write_i32(&mut content, blocks.len() as i32)?;
+    for i in 0..blocks.len() {
+        write_i32(&mut content, 2 + i as i32 * 2)?;
+    }
+    write_chunk(&mut children, b"nGRP", &content)?;
+
+    for (i, block) in blocks.iter().enumerate() {
+        let block_v = Vec3::new(block.0, block.1, block.2);
+        let origin = min + block_v * MAX_MODEL_SIZE;
+        let size = (max - min - block_v * MAX_MODEL_SIZE + 1).map(|s| s.min(MAX_MODEL_SIZE));
+        // The importer (and MagicaVoxel) centers a model at its translation
+        let t = origin + size.map(|s| s / 2);
+        let transform_id = 2 + i as i32 * 2;
+
+        let mut content = vec![];
+        write_i32(&mut content, transform_id)?;
+        write_dict(&mut content, &[])?;
+        write_i32(&mut content, transform_id + 1)?;
+        write_i32(&mut content, -1)?;
+        write_i32(&mut content, 0)?;
+        write_i32(&mut content, 1)?;
+        write_dict(
+            &mut content,
+            &[("_t".into(), format!("{} {} {}", t.x, t.y, t.z))],
+        )?;
+        write_chunk(&mut children, b"nTRN", &content)?;
+
+        let mut content = vec![];
+        write_i32(&mut content, transform_id + 1)?;
+        write_dict(&mut content, &[])?;
+        write_i32(&mut content, 1)?;
+        write_i32(&mut content, i as i32)?;
+        write_dict(&mut content, &[])?;
+        write_chunk(&mut children, b"nSHP", &content)?;
+    }
+
+    // Palette
+    let mut content = vec![];
+    for source in source_material.iter().skip(1) {
+        let c = palette
+            .get(*source)
+            .base_color
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        content.extend_from_slice(&[c.x, c.y, c.z, 255]);
+    }
+    content.extend_from_slice(&[0, 0, 0, 0]);
+    write_chunk(&mut children, b"RGBA", &content)?;
+
+    for (index, source) in source_material.iter().enumerate().skip(1) {
+        let mut content = vec![];
+        write_i32(&mut content, index as i32)?;
+        write_dict(&mut content, &matl_properties(palette.get(*source)))?;
+        write_chunk(&mut children, b"MATL", &content)?;
+    }
+
+    writer.write_all(b"VOX ")?;
+    write_i32(writer, 200)?;
+    writer.write_all(b"MAIN")?;
+    write_i32(writer, 0)?;
+    write_i32(writer, children.len() as i32)?;
+    writer.write_all(&children)
+}
+
+/// The MATL properties describing the material, the inverse of `apply_matl`.
+fn matl_properties(material: &Material) -> Vec<(String, String)> {
+    let mut props = vec![];
+
+    let kind = if material.emission_strength > 0.0 {
+        "_emit"
+    } else if material.transmission > 0.0 {
+        "_glass"
+    } else if material.metallic > 0.0 {
+        "_metal"
+    } else {
+        "_diffuse"
+    };
+    props.push(("_type".into(), kind.into()));
+    props.push(("_rough".into(), material.roughness.to_string()));
+    props.push(("_sp".into(), material.specular.to_string()));
+
+    match kind {
+        "_emit" => {
+            let strength = material.emission_strength;
+            let (emit, flux) = if strength <= 1.0 {
+                (strength, 0.0)
+            } else {
+                (1.0, strength.log2().min(4.0))
+            };
+            props.push(("_emit".into(), emit.to_string()));
+            props.push(("_flux".into(), flux.to_string()));
+        }
+        "_glass" => {
+            props.push(("_trans".into(), material.transmission.to_string()));
+            props.push(("_ior".into(), (material.ior - 1.0).max(0.0).to_string()));
+        }
+        "_metal" => {
+            props.push(("_metal".into(), material.metallic.to_string()));
+        }
+        _ => {}
+    }
+
+    props
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) -> Result<()> {
+    out.extend_from_slice(id);
+    write_i32(out, content.len() as i32)?;
+    write_i32(out, 0)?;
+    out.extend_from_slice(content);
+    Ok(())
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) -> Result<()> {
+    write_i32(out, string.len() as i32)?;
+    out.extend_from_slice(string.as_bytes());
+    Ok(())
+}
+
+fn write_dict(out: &mut Vec<u8>, dict: &[(String, String)]) -> Result<()> {
+    write_i32(out, dict.len() as i32)?;
+    for (key, value) in dict {
+        write_string(out, key)?;
+        write_string(out, value)?;
+    }
+    Ok(())
+}
+
+/// Map the properties of a MATL chunk onto the material.
+fn apply_matl(material: &mut Material, color: Vec3<F>, props: &FxHashMap<String, String>) {
+    let get = |key: &str| props.get(key).and_then(|v| v.parse::<F>().ok());
+
+    if let Some(rough) = get("_rough") {
+        material.roughness = rough.clamp(0.0, 1.0);
+    }
+    if let Some(spec) = get("_sp").or_else(|| get("_spec")) {
+        material.specular = spec.clamp(0.0, 1.0);
+    }
+
+    match props.get("_type").map(|t| t.as_str()) {
+        Some("_metal") | Some("_blend") => {
+            material.metallic = get("_metal").unwrap_or(0.0).clamp(0.0, 1.0);
+        }
+        Some("_glass") => {
+            material.transmission = get("_trans")
+                .or_else(|| get("_alpha"))
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            // MagicaVoxel stores the index of refraction minus one
+            if let Some(ior) = get("_ior") {
+                material.ior = 1.0 + ior.max(0.0);
+            }
+            material.transmission_roughness = material.roughness;
+        }
+        Some("_emit") => {
+            let emit = get("_emit").unwrap_or(0.0).max(0.0);
+            let flux = get("_flux").unwrap_or(0.0).max(0.0);
+            material.emission_color = color;
+            material.emission_strength = emit * (2.0 as F).powf(flux);
+        }
+        _ => {}
+    }
+}
+
+/// Recursively collect the (model, rotation, translation) instances of the scene graph.
+fn collect_instances(
+    nodes: &FxHashMap<i32, Node>,
+    id: i32,
+    rotation: Matrix,
+    translation: [i32; 3],
+    instances: &mut Vec<Instance>,
+    depth: usize,
+) {
+    // Guard against cyclic graphs in broken files
+    if depth > 64 {
+        return;
+    }
+
+    match nodes.get(&id) {
+        Some(Node::Transform {
+            child,
+            rotation: r,
+            translation: t,
+        }) => {
+            let rotation_child = mul(&rotation, r);
+            let translation_child = transform(&rotation, translation, *t);
+            collect_instances(
+                nodes,
+                *child,
+                rotation_child,
+                translation_child,
+                instances,
+                depth + 1,
+            );
+        }
+        Some(Node::Group { children }) => {
+            for child in children {
+                collect_instances(nodes, *child, rotation, translation, instances, depth + 1);
+            }
+        }
+        Some(Node::Shape { models }) => {
+            for model in models {
+                if *model >= 0 {
+                    instances.push((*model as usize, rotation, translation));
+                }
+            }
+        }
+        None => {}
+    }
+}
+
+/// Decode the packed rotation byte of a nTRN frame into a matrix.
+fn decode_rotation(r: u8) -> Matrix {
+    let first = (r & 3) as usize;
+    let second = ((r >> 2) & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return IDENTITY;
+    }
+    let third = 3 - first - second;
+
+    let mut m = [[0; 3]; 3];
+    m[0][first] = if r & (1 << 4) != 0 { -1 } else { 1 };
+    m[1][second] = if r & (1 << 5) != 0 { -1 } else { 1 };
+    m[2][third] = if r & (1 << 6) != 0 { -1 } else { 1 };
+    m
+}
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0; 3]; 3];
+    for (row, m_row) in m.iter_mut().enumerate() {
+        for (col, value) in m_row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[row][k] * b[k][col]).sum();
+        }
+    }
+    m
+}
+
+fn transform(rotation: &Matrix, translation: [i32; 3], p: [i32; 3]) -> [i32; 3] {
+    let mut out = translation;
+    for (row, value) in out.iter_mut().enumerate() {
+        *value += (0..3).map(|k| rotation[row][k] * p[k]).sum::<i32>();
+    }
+    out
+}
+
+/// The MagicaVoxel default palette, indexed by colour index.
+pub fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0; 4]; 256];
+    let mut index = 1;
+
+    // 6x6x6 colour cube without black
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                if r == 0 && g == 0 && b == 0 {
+                    continue;
+                }
+                palette[index] = [r, g, b, 0xff];
+                index += 1;
+            }
+        }
+    }
+
+    // Red, green, blue and grey ramps
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in 0..4 {
+        for v in ramp {
+            palette[index] = match channel {
+                0 => [v, 0, 0, 0xff],
+                1 => [0, v, 0, 0xff],
+                2 => [0, 0, v, 0xff],
+                _ => [v, v, v, 0xff],
+            };
+            index += 1;
+        }
+    }
+
+    palette
+}
+
+fn read_exact(data: &mut &[u8], buf: &mut [u8]) -> Result<()> {
+    std::io::Read::read_exact(data, buf)
+}
+
+fn read_chunk<'a>(data: &mut &'a [u8]) -> Result<Chunk<'a>> {
+    let mut id = [0; 4];
+    read_exact(data, &mut id)?;
+    let content_size = read_i32(data)?.max(0) as usize;
+    let children_size = read_i32(data)?.max(0) as usize;
+
+    if data.len() < content_size + children_size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "truncated chunk"));
+    }
+
+    let (content, rest) = data.split_at(content_size);
+    let (children, rest) = rest.split_at(children_size);
+    *data = rest;
+
+    Ok(Chunk {
+        id,
+        content,
+        children,
+    })
+}
+
+fn read_string(data: &mut &[u8]) -> Result<String> {
+    let length = read_i32(data)?.max(0) as usize;
+    if data.len() < length {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "truncated string"));
+    }
+    let (bytes, rest) = data.split_at(length);
+    *data = rest;
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn read_dict(data: &mut &[u8]) -> Result<FxHashMap<String, String>> {
+    let count = read_i32(data)?.max(0);
+    let mut dict = FxHashMap::default();
+    for _ in 0..count {
+        let key = read_string(data)?;
+        let value = read_string(data)?;
+        dict.insert(key, value);
+    }
+    Ok(dict)
+}
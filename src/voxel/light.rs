@@ -0,0 +1,172 @@
+use crate::F_PI;
+use crate::prelude::*;
+use vek::{Vec2, Vec3};
+
+/// The shape of a light.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LightType {
+    /// An infinitely small light at the position.
+    Point { position: Vec3<F> },
+    /// A spherical light, soft shadows depend on the radius.
+    Sphere { position: Vec3<F>, radius: F },
+    /// A light at infinity shining along -direction (the sun). The angle is the angular radius
+    /// of the light disc in radians, 0 gives hard shadows.
+    Directional { direction: Vec3<F>, angle: F },
+    /// A one-sided rectangular area light. The light is emitted along `u x v`.
+    Rect {
+        center: Vec3<F>,
+        u: Vec3<F>,
+        v: Vec3<F>,
+    },
+}
+
+/// A light source of the scene.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Light {
+    pub light_type: LightType,
+    /// The linear color of the light.
+    pub color: Vec3<F>,
+    /// Radiance for area and sphere lights, intensity for point lights and irradiance for
+    /// directional lights.
+    pub intensity: F,
+}
+
+/// A sample towards a light.
+pub struct LightSample {
+    /// The normalized direction from the shaded point to the light.
+    pub direction: Vec3<F>,
+    /// The distance to the sampled point on the light (F::MAX for directional lights).
+    pub distance: F,
+    /// The incoming radiance along the direction.
+    pub radiance: Vec3<F>,
+    /// The solid angle pdf of the sample, 1 for delta lights.
+    pub pdf: F,
+}
+
+impl Light {
+    pub fn new(light_type: LightType, color: Vec3<F>, intensity: F) -> Self {
+        Self {
+            light_type,
+            color,
+            intensity,
+        }
+    }
+
+    /// Sample the light from the point `p` with two uniform random numbers.
+    pub fn sample(&self, p: Vec3<F>, rnd: Vec2<F>) -> Option<LightSample> {
+        let emitted = self.color * self.intensity;
+
+        match &self.light_type {
+            LightType::Point { position } => {
+                let to_light = *position - p;
+                let distance_sq = to_light.magnitude_squared();
+                if distance_sq <= 0.0 {
+                    return None;
+                }
+                let distance = distance_sq.sqrt();
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: emitted / distance_sq,
+                    pdf: 1.0,
+                })
+            }
+            LightType::Sphere { position, radius } => {
+                let to_light = *position - p;
+                let distance_sq = to_light.magnitude_squared();
+                if distance_sq <= radius * radius {
+                    return None;
+                }
+
+                // Uniformly sample the cone of directions covered by the sphere
+                let cos_max = (1.0 - radius * radius / distance_sq).max(0.0).sqrt();
+                let direction = sample_cone(to_light, cos_max, rnd);
+                let ray = Ray::new(p, direction);
+                let distance = ray
+                    .intersect_sphere(*position, *radius)
+                    .unwrap_or(distance_sq.sqrt());
+
+                Some(LightSample {
+                    direction,
+                    distance,
+                    radiance: emitted,
+                    pdf: 1.0 / (2.0 * F_PI * (1.0 - cos_max)).max(1e-8),
+                })
+            }
+            LightType::Directional { direction, angle } => {
+                let direction = direction.normalized();
+                if *angle <= 0.0 {
+                    return Some(LightSample {
+                        direction,
+                        distance: F::MAX,
+                        radiance: emitted,
+                        pdf: 1.0,
+                    });
+                }
+
+                // Keep the irradiance independent of the size of the disc
+                let cos_max = angle.min(F_PI * 0.5).cos();
+                let solid_angle = 2.0 * F_PI * (1.0 - cos_max);
+                Some(LightSample {
+                    direction: sample_cone(direction, cos_max, rnd),
+                    distance: F::MAX,
+                    radiance: emitted / solid_angle,
+                    pdf: 1.0 / solid_angle,
+                })
+            }
+            LightType::Rect { center, u, v } => {
+                let normal = u.cross(*v);
+                let area = normal.magnitude();
+                if area <= 0.0 {
+                    return None;
+                }
+                let normal = normal / area;
+
+                let point = *center + *u * (rnd.x - 0.5) + *v * (rnd.y - 0.5);
+                let to_light = point - p;
+                let distance_sq = to_light.magnitude_squared();
+                let distance = distance_sq.sqrt();
+                let direction = to_light / distance;
+
+                let cos_light = -direction.dot(normal);
+                if cos_light <= 0.0 {
+                    return None;
+                }
+
+                Some(LightSample {
+                    direction,
+                    distance,
+                    radiance: emitted,
+                    pdf: distance_sq / (cos_light * area),
+                })
+            }
+        }
+    }
+
+    /// True for lights which cannot be hit by a ray (point and hard directional lights).
+    pub fn is_delta(&self) -> bool {
+        match &self.light_type {
+            LightType::Point { .. } => true,
+            LightType::Directional { angle, .. } => *angle <= 0.0,
+            _ => false,
+        }
+    }
+}
+
+/// Uniformly sample a direction inside the cone around `axis` with the given cosine.
+fn sample_cone(axis: Vec3<F>, cos_max: F, rnd: Vec2<F>) -> Vec3<F> {
+    let w = axis.normalized();
+    let helper = if w.x.abs() > 0.9 {
+        Vec3::unit_y()
+    } else {
+        Vec3::unit_x()
+    };
+    let u = helper.cross(w).normalized();
+    let v = w.cross(u);
+
+    let cos_theta = 1.0 - rnd.x * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * F_PI * rnd.y;
+
+    ((u * phi.cos() + v * phi.sin()) * sin_theta + w * cos_theta).normalized()
+}
@@ -1,9 +1,20 @@
+pub mod aov;
+pub mod background;
 pub mod camera;
+pub mod color;
+pub mod denoise;
+pub mod emitters;
+pub mod environment;
+pub mod fog;
 pub mod grid;
+pub mod io;
+pub mod light;
 pub mod palette;
 pub mod ray;
 pub mod renderbuffer;
 pub mod renderer;
+pub mod scene;
+pub mod sky;
 pub mod tile;
 
 use crate::F;
@@ -2,6 +2,7 @@
 #![allow(clippy::upper_case_acronyms)]
 
 use crate::prelude::*;
+use crate::voxel::color::srgb_to_linear;
 use serde::{Deserialize, Serialize};
 use theframework::prelude::*;
 use vek::Vec3;
@@ -105,19 +106,20 @@ impl Default for Material {
 
 impl Material {
     pub fn base_color_linear(&self) -> Vec3<F> {
-        fn srgb_to_linear(c: F) -> F {
-            if c <= 0.04045 {
-                c / 12.92
-            } else {
-                ((c + 0.055) / 1.055).powf(2.4)
-            }
-        }
+        srgb_to_linear(self.base_color)
+    }
+
+    /// True if light transmitted into the material travels through a participating medium.
+    pub fn has_volume(&self) -> bool {
+        self.transmission > 0.0
+            && (self.transmission_depth.is_some()
+                || self.volume_scatter_color.is_some()
+                || self.volume_absorption_color.is_some())
+    }
 
-        Vec3::new(
-            srgb_to_linear(self.base_color.x),
-            srgb_to_linear(self.base_color.y),
-            srgb_to_linear(self.base_color.z),
-        )
+    /// True if the material does not bend transmitted light (an ior of 1, e.g. smoke).
+    pub fn is_index_matched(&self) -> bool {
+        self.ior <= 1.0
     }
 }
 
@@ -156,6 +158,85 @@ impl Palette {
         &mut self.materials[index as usize]
     }
 
+    /// Set the base color of a material and keep its color node in sync.
+    pub fn set_base_color(&mut self, index: u8, color: Vec3<F>) {
+        self.materials[index as usize].base_color = color;
+        if let Some(node) = self.graphs[index as usize].nodes.first_mut() {
+            node.values[0] = color.x;
+            node.values[1] = color.y;
+            node.values[2] = color.z;
+        }
+    }
+
+    /// Map sRGB colours (with their usage counts) onto palette indices.
+    ///
+    /// Colours within `tolerance` of an existing entry reuse that entry. The remaining colours
+    /// are written into the entries not flagged in `used`; if there are not enough of them the
+    /// colours are reduced by a median cut first. Returns the palette index of every colour.
+    pub fn quantize(
+        &mut self,
+        colors: &[(Vec3<F>, usize)],
+        used: &[bool; 256],
+        tolerance: F,
+    ) -> Vec<u8> {
+        let mut reserved = *used;
+        let mut result = vec![0_u8; colors.len()];
+        let mut unmatched = vec![];
+
+        for (i, (color, _)) in colors.iter().enumerate() {
+            let (index, distance) = self.closest(*color);
+            if distance <= tolerance * tolerance {
+                result[i] = index;
+                reserved[index as usize] = true;
+            } else {
+                unmatched.push(i);
+            }
+        }
+
+        let free: Vec<u8> = (0..=255_u8).filter(|i| !reserved[*i as usize]).collect();
+
+        if unmatched.len() <= free.len() {
+            for (i, index) in unmatched.iter().zip(&free) {
+                self.materials[*index as usize] = Material::default();
+                self.set_base_color(*index, colors[*i].0);
+                result[*i] = *index;
+            }
+        } else if !free.is_empty() {
+            for (group, index) in median_cut(colors, unmatched, free.len()).iter().zip(&free) {
+                let weight: usize = group.iter().map(|i| colors[*i].1.max(1)).sum();
+                let mean = group
+                    .iter()
+                    .map(|i| colors[*i].0 * colors[*i].1.max(1) as F)
+                    .sum::<Vec3<F>>()
+                    / weight as F;
+
+                self.materials[*index as usize] = Material::default();
+                self.set_base_color(*index, mean);
+                for i in group {
+                    result[*i] = *index;
+                }
+            }
+        } else {
+            for i in unmatched {
+                result[i] = self.closest(colors[i].0).0;
+            }
+        }
+
+        result
+    }
+
+    /// The index of the entry with the closest base colour and the squared distance to it.
+    pub fn closest(&self, color: Vec3<F>) -> (u8, F) {
+        let mut best = (0, F::MAX);
+        for (index, material) in self.materials.iter().enumerate() {
+            let distance = (material.base_color - color).magnitude_squared();
+            if distance < best.1 {
+                best = (index as u8, distance);
+            }
+        }
+        best
+    }
+
     /// Fill this palette from the text of a Paint-NET “.txt” palette file.
     ///
     /// * Existing materials (0-based) are overwritten with the parsed colours.
@@ -196,10 +277,7 @@ impl Palette {
             let b = (argb & 0xFF) as f32 / 255.0;
 
             if idx < self.materials.len() {
-                self.materials[idx].base_color = Vec3::new(r, g, b);
-                self.graphs[idx].nodes[0].values[0] = r;
-                self.graphs[idx].nodes[0].values[1] = g;
-                self.graphs[idx].nodes[0].values[2] = b;
+                self.set_base_color(idx as u8, Vec3::new(r, g, b));
 
                 idx += 1;
             } else {
@@ -210,3 +288,53 @@ impl Palette {
         Ok(())
     }
 }
+
+/// Split the given colours into at most `count` groups by repeatedly halving the group with
+/// the largest extent at the weighted median of its widest channel.
+fn median_cut(colors: &[(Vec3<F>, usize)], indices: Vec<usize>, count: usize) -> Vec<Vec<usize>> {
+    let extent = |group: &[usize]| -> (usize, F) {
+        let mut min = Vec3::broadcast(F::MAX);
+        let mut max = Vec3::broadcast(F::MIN);
+        for i in group {
+            min = Vec3::partial_min(min, colors[*i].0);
+            max = Vec3::partial_max(max, colors[*i].0);
+        }
+        let size = max - min;
+        (0..3)
+            .map(|axis| (axis, size[axis]))
+            .fold((0, -1.0), |a, b| if b.1 > a.1 { b } else { a })
+    };
+
+    let mut groups = vec![indices];
+    while groups.len() < count {
+        let Some((split, (axis, _))) = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| group.len() > 1)
+            .map(|(i, group)| (i, extent(group)))
+            .max_by(|a, b| a.1.1.total_cmp(&b.1.1))
+        else {
+            break;
+        };
+
+        let mut group = groups.swap_remove(split);
+        group.sort_by(|a, b| colors[*a].0[axis].total_cmp(&colors[*b].0[axis]));
+
+        let total: usize = group.iter().map(|i| colors[*i].1.max(1)).sum();
+        let mut acc = 0;
+        let mut middle = 1;
+        for (n, i) in group.iter().enumerate() {
+            acc += colors[*i].1.max(1);
+            if acc * 2 >= total {
+                middle = (n + 1).clamp(1, group.len() - 1);
+                break;
+            }
+        }
+
+        let upper = group.split_off(middle);
+        groups.push(group);
+        groups.push(upper);
+    }
+
+    groups
+}
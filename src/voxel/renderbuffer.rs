@@ -1,4 +1,49 @@
 use crate::prelude::*;
+use crate::voxel::aov::{Aov, AovSample};
+use crate::voxel::color;
+use vek::Vec3;
+
+/// How the linear HDR values of the buffer are mapped to 8-bit output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ToneMapping {
+    /// Clamp the values, no conversion.
+    Linear,
+    /// Clamp and apply the sRGB transfer function.
+    #[default]
+    Srgb,
+    /// ACES filmic curve (Narkowicz fit), followed by the sRGB transfer function.
+    Filmic,
+}
+
+impl ToneMapping {
+    /// Map a linear channel value to [0, 1].
+    #[inline]
+    pub fn apply(&self, x: F) -> F {
+        fn tonemap_film(x: F) -> F {
+            let a = 2.51;
+            let b = 0.03;
+            let c = 2.43;
+            let d = 0.59;
+            let e = 0.14;
+            ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+        }
+
+        fn linear_to_srgb(x: F) -> F {
+            if x <= 0.0031308 {
+                x * 12.92
+            } else {
+                1.055 * x.powf(1.0 / 2.4) - 0.055
+            }
+        }
+
+        let x = x.max(0.0);
+        match self {
+            ToneMapping::Linear => x.min(1.0),
+            ToneMapping::Srgb => linear_to_srgb(x.min(1.0)),
+            ToneMapping::Filmic => linear_to_srgb(tonemap_film(x)),
+        }
+    }
+}
 
 /// A color buffer holding an array of float pixels.
 #[derive(PartialEq, Debug, Clone)]
@@ -6,7 +51,17 @@ pub struct RenderBuffer {
     pub width: usize,
     pub height: usize,
     pub pixels: Vec<F>,
+    /// The accumulation pass, 1 restarts the accumulation.
     pub accum: u32,
+    /// The number of samples accumulated in each pixel.
+    pub samples: Vec<u32>,
+    /// The running mean of the squared luminance of each pixel, for the noise estimate.
+    pub moments: Vec<F>,
+    /// The AOV passes, `Aov::CHANNELS` floats per pixel. The passes average the samples
+    /// which hit a voxel, pixels without hits keep the passes of a miss.
+    pub aovs: Vec<F>,
+    /// The number of accumulated samples of each pixel which hit a voxel.
+    pub hits: Vec<u32>,
 
     pub file_path: Option<std::path::PathBuf>,
 }
@@ -18,10 +73,22 @@ impl RenderBuffer {
             height,
             pixels: vec![0.0; width * height * 4],
             accum: 1,
+            samples: vec![0; width * height],
+            moments: vec![0.0; width * height],
+            aovs: AovSample::default().to_array().repeat(width * height),
+            hits: vec![0; width * height],
             file_path: None,
         }
     }
 
+    /// Restart the accumulation, the pixels are overwritten by the next samples.
+    pub fn reset_accum(&mut self) {
+        self.accum = 1;
+        self.samples.fill(0);
+        self.moments.fill(0.0);
+        self.hits.fill(0);
+    }
+
     /// Get the color of a pixel
     #[inline(always)]
     pub fn at(&self, x: usize, y: usize) -> Color {
@@ -40,6 +107,20 @@ impl RenderBuffer {
         self.pixels[i..i + 4].copy_from_slice(&color);
     }
 
+    /// Set the AOV passes of a pixel
+    pub fn set_aov(&mut self, x: usize, y: usize, sample: &AovSample) {
+        let pixel = y * self.width + x;
+        let i = pixel * Aov::CHANNELS;
+        self.aovs[i..i + Aov::CHANNELS].copy_from_slice(&sample.to_array());
+        self.hits[pixel] = sample.material.is_some() as u32;
+    }
+
+    /// True if any sample of the pixel hit a voxel.
+    #[inline(always)]
+    pub fn is_hit(&self, x: usize, y: usize) -> bool {
+        self.hits[y * self.width + x] > 0
+    }
+
     /// Copy the pixels from another buffer to this buffer
     pub fn copy_from(&mut self, x: usize, y: usize, other: &RenderBuffer) {
         for local_y in 0..other.height {
@@ -55,11 +136,19 @@ impl RenderBuffer {
                 let local_index = (local_y * other.width + local_x) * 4;
                 self.pixels[index..index + 4]
                     .copy_from_slice(&other.pixels[local_index..local_index + 4]);
+
+                let index = (global_y * self.width + global_x) * Aov::CHANNELS;
+                let local_index = (local_y * other.width + local_x) * Aov::CHANNELS;
+                self.aovs[index..index + Aov::CHANNELS]
+                    .copy_from_slice(&other.aovs[local_index..local_index + Aov::CHANNELS]);
+                self.hits[global_y * self.width + global_x] =
+                    other.hits[local_y * other.width + local_x];
             }
         }
     }
 
-    /// Copy and accumulate pixels from another buffer to this buffer
+    /// Copy and accumulate pixels from another buffer to this buffer, every pixel of
+    /// `other` is one more sample of the pixel.
     pub fn accum_from(&mut self, x: usize, y: usize, other: &RenderBuffer) {
         for local_y in 0..other.height {
             for local_x in 0..other.width {
@@ -70,17 +159,176 @@ impl RenderBuffer {
                     continue;
                 }
 
-                let index = (global_y * self.width + global_x) * 4;
+                let pixel = global_y * self.width + global_x;
+                let index = pixel * 4;
                 let local_index = (local_y * other.width + local_x) * 4;
 
+                self.samples[pixel] += 1;
+                let factor = 1.0 / self.samples[pixel] as f32;
+
                 for i in 0..4 {
                     let old = self.pixels[index + i];
                     let new = other.pixels[local_index + i];
-                    let factor = 1.0 / self.accum as f32;
                     self.pixels[index + i] = old * (1.0 - factor) + new * factor;
                 }
+
+                let new = &other.pixels[local_index..local_index + 3];
+                let luminance = color::luminance(Vec3::new(new[0], new[1], new[2]));
+                self.moments[pixel] =
+                    self.moments[pixel] * (1.0 - factor) + luminance * luminance * factor;
+
+                // The geometric passes only average the samples which hit a voxel, the
+                // first hit replaces the passes of the misses before it
+                let index = pixel * Aov::CHANNELS;
+                let local_index = (local_y * other.width + local_x) * Aov::CHANNELS;
+                let local_hits = other.hits[local_y * other.width + local_x];
+                if self.samples[pixel] == 1 {
+                    self.hits[pixel] = 0;
+                    self.aovs[index..index + Aov::CHANNELS]
+                        .copy_from_slice(&other.aovs[local_index..local_index + Aov::CHANNELS]);
+                }
+                if local_hits == 0 {
+                    continue;
+                }
+                self.hits[pixel] += local_hits;
+                if self.hits[pixel] == local_hits {
+                    self.aovs[index..index + Aov::CHANNELS]
+                        .copy_from_slice(&other.aovs[local_index..local_index + Aov::CHANNELS]);
+                    continue;
+                }
+
+                let factor = local_hits as F / self.hits[pixel] as F;
+                for aov in Aov::ALL.into_iter().filter(Aov::accumulates) {
+                    for c in aov.offset()..aov.offset() + aov.channels() {
+                        let old = self.aovs[index + c];
+                        let new = other.aovs[local_index + c];
+                        self.aovs[index + c] = old * (1.0 - factor) + new * factor;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The variance of the mean luminance of a pixel, the squared standard error of its
+    /// estimate. Infinite below two samples.
+    pub fn variance(&self, x: usize, y: usize) -> F {
+        let pixel = y * self.width + x;
+        let n = self.samples[pixel];
+        if n < 2 {
+            return F::INFINITY;
+        }
+
+        let mean = self.luminance(x, y);
+        let variance = (self.moments[pixel] - mean * mean).max(0.0) * n as F / (n - 1) as F;
+        variance / n as F
+    }
+
+    /// The noise of a pixel: the standard error of its luminance relative to the square root
+    /// of the luminance, roughly the perceived noise. Infinite below two samples.
+    pub fn noise(&self, x: usize, y: usize) -> F {
+        self.variance(x, y).sqrt() / self.luminance(x, y).max(0.0).sqrt().max(0.01)
+    }
+
+    /// The luminance of a pixel.
+    #[inline(always)]
+    pub fn luminance(&self, x: usize, y: usize) -> F {
+        let i = (y * self.width + x) * 4;
+        color::luminance(Vec3::new(
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+        ))
+    }
+
+    /// The highest noise of the pixels inside the rectangle.
+    pub fn max_noise(&self, x: usize, y: usize, width: usize, height: usize) -> F {
+        let mut noise: F = 0.0;
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                noise = noise.max(self.noise(x, y));
+            }
+        }
+        noise
+    }
+
+    /// The value of a pass at a pixel, single channel passes are broadcast.
+    #[inline(always)]
+    pub fn aov_at(&self, x: usize, y: usize, aov: Aov) -> [F; 3] {
+        let i = (y * self.width + x) * Aov::CHANNELS + aov.offset();
+        if aov.channels() == 1 {
+            [self.aovs[i]; 3]
+        } else {
+            [self.aovs[i], self.aovs[i + 1], self.aovs[i + 2]]
+        }
+    }
+
+    /// The raw values of a pass as a buffer, for export. Misses have infinite depth.
+    pub fn aov(&self, aov: Aov) -> RenderBuffer {
+        let mut buffer = RenderBuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let [r, g, b] = self.aov_at(x, y, aov);
+                buffer.set(x, y, [r, g, b, 1.0]);
+            }
+        }
+        buffer
+    }
+
+    /// A pass mapped to displayable colors in [0, 1]: near depth is bright, normals are
+    /// mapped from [-1, 1], palette indices get distinct colors and positions are scaled to
+    /// the bounds of the visible hits. Misses are black.
+    pub fn aov_preview(&self, aov: Aov) -> RenderBuffer {
+        let mut buffer = RenderBuffer::new(self.width, self.height);
+
+        // The bounds of the hits
+        let mut min = Vec3::broadcast(F::MAX);
+        let mut max = Vec3::broadcast(F::MIN);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.is_hit(x, y) {
+                    let depth = self.aov_at(x, y, Aov::Depth)[0];
+                    let position = Vec3::from(self.aov_at(x, y, Aov::Position));
+                    let value = if aov == Aov::Depth {
+                        Vec3::broadcast(depth)
+                    } else {
+                        position
+                    };
+                    min = min.map2(value, F::min);
+                    max = max.map2(value, F::max);
+                }
+            }
+        }
+        let extent = (max - min).map(|v| v.max(1e-6));
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !self.is_hit(x, y) {
+                    buffer.set(x, y, [0.0, 0.0, 0.0, 1.0]);
+                    continue;
+                }
+                let value = Vec3::from(self.aov_at(x, y, aov));
+                let c = match aov {
+                    Aov::Depth => Vec3::broadcast(1.0 - (value.x - min.x) / extent.x * 0.9),
+                    Aov::Normal => value * 0.5 + 0.5,
+                    Aov::Albedo => value,
+                    Aov::MaterialId => {
+                        // Spread the indices around the hue circle
+                        let hue = (value.x * 0.618_034).fract() * 6.0;
+                        let rgb = Vec3::new(
+                            (hue - 3.0).abs() - 1.0,
+                            2.0 - (hue - 2.0).abs(),
+                            2.0 - (hue - 4.0).abs(),
+                        );
+                        rgb.map(|v| v.clamp(0.0, 1.0)) * 0.8 + 0.2
+                    }
+                    Aov::Position => (value - min) / extent,
+                };
+                let c = c.map(|v| v.clamp(0.0, 1.0));
+                buffer.set(x, y, [c.x, c.y, c.z, 1.0]);
             }
         }
+
+        buffer
     }
 
     /// Convert the frame to an u8 vec, applying gamma correction
@@ -145,79 +393,56 @@ impl RenderBuffer {
         out
     }
 
-    /*
-    /// Save the buffer to a file as PNG
-    pub fn save(&self, path: std::path::PathBuf) {
-        let mut image = image::ImageBuffer::new(self.width as u32, self.height as u32);
+    /// Convert the frame to an RGBA u8 vec using the given tone mapping.
+    pub fn to_u8_vec_tonemapped(&self, mapping: ToneMapping) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![0; self.width * self.height * 4];
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let i = y * self.width * 4 + x * 4;
-                let c = image::Rgb([
-                    (self.pixels[i] * 255.0) as u8,
-                    (self.pixels[i + 1] * 255.0) as u8,
-                    (self.pixels[i + 2] * 255.0) as u8,
-                ]);
-                image.put_pixel(x as u32, y as u32, c);
+        for (d, s) in out.chunks_exact_mut(4).zip(self.pixels.chunks_exact(4)) {
+            for c in 0..3 {
+                d[c] = (mapping.apply(s[c]) * 255.0).round() as u8;
             }
+            d[3] = (s[3].clamp(0.0, 1.0) * 255.0).round() as u8;
         }
 
-        image.save(path).unwrap();
+        out
     }
 
-    pub fn save_srgb(&self, path: std::path::PathBuf) {
-        let gamma = 1.0 / 2.2;
-        let mut image = image::ImageBuffer::new(self.width as u32, self.height as u32);
-
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let i = y * self.width * 4 + x * 4;
-
-                let r = self.pixels[i].max(0.0).powf(gamma);
-                let g = self.pixels[i + 1].max(0.0).powf(gamma);
-                let b = self.pixels[i + 2].max(0.0).powf(gamma);
-
-                let rgb = image::Rgb([
-                    (r * 255.0).min(255.0) as u8,
-                    (g * 255.0).min(255.0) as u8,
-                    (b * 255.0).min(255.0) as u8,
-                ]);
-                image.put_pixel(x as u32, y as u32, rgb);
-            }
+    /// Save the buffer to a file as an RGBA PNG using the given tone mapping.
+    pub fn save_png(&self, path: &std::path::Path, mapping: ToneMapping) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let mut encoder = png::Encoder::new(
+            std::io::BufWriter::new(file),
+            self.width as u32,
+            self.height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        if mapping != ToneMapping::Linear {
+            encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
         }
 
-        image.save(path).unwrap();
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_u8_vec_tonemapped(mapping))?;
+        writer.finish()?;
+
+        Ok(())
     }
 
-    pub fn save_film(&self, path: std::path::PathBuf) {
-        fn tonemap_film(x: F) -> F {
-            let a = 2.51;
-            let b = 0.03;
-            let c = 2.43;
-            let d = 0.59;
-            let e = 0.14;
-            ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
-        }
+    /// Save the un-clamped buffer as a Radiance `.hdr` file (without alpha).
+    pub fn save_hdr(&self, path: &std::path::Path) -> std::io::Result<()> {
+        use std::io::Write;
 
-        let mut image = image::ImageBuffer::new(self.width as u32, self.height as u32);
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        crate::voxel::io::hdr::write_hdr(&mut writer, self.width, self.height, &self.pixels)?;
+        writer.flush()
+    }
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let i = y * self.width * 4 + x * 4;
-
-                let r = tonemap_film(self.pixels[i]);
-                let g = tonemap_film(self.pixels[i + 1]);
-                let b = tonemap_film(self.pixels[i + 2]);
-
-                let rgb = image::Rgb([
-                    (r * 255.0).min(255.0) as u8,
-                    (g * 255.0).min(255.0) as u8,
-                    (b * 255.0).min(255.0) as u8,
-                ]);
-                image.put_pixel(x as u32, y as u32, rgb);
-            }
-        }
+    /// Save the un-clamped buffer including alpha as an OpenEXR file.
+    pub fn save_exr(&self, path: &std::path::Path) -> std::io::Result<()> {
+        use std::io::Write;
 
-        image.save(path).unwrap();
-    }*/
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        crate::voxel::io::exr::write_exr(&mut writer, self.width, self.height, &self.pixels)?;
+        writer.flush()
+    }
 }
@@ -0,0 +1,548 @@
+use crate::prelude::*;
+use crate::voxel::color::luminance;
+use crate::voxel::renderer::settings::Scatter;
+use crate::{F_PI, F_TAU};
+use rand::Rng;
+use vek::Vec3;
+
+/// Transmission roughness (alpha) below which the dielectric is treated as perfectly smooth.
+const SMOOTH_ALPHA: F = 1e-3;
+
+/// The smallest alpha used for the rough lobes, keeps the GGX terms finite.
+const MIN_ALPHA: F = 1e-3;
+
+/// A direction sampled from the BSDF.
+pub struct BsdfSample {
+    pub direction: Vec3<F>,
+    /// The BSDF times the cosine divided by the pdf.
+    pub weight: Vec3<F>,
+    /// The solid angle pdf, None for the smooth (delta) transmission lobe.
+    pub pdf: Option<F>,
+    /// The kind of the sampled lobe, counted against the bounce limits.
+    pub scatter: Scatter,
+}
+
+/// The layered principled BSDF of a material at a surface point, built from all `Material`
+/// parameters: Burley diffuse with subsurface and sheen, anisotropic GGX specular with
+/// tint, edge color and thin-film interference, rough or smooth dielectric transmission and
+/// a clearcoat / coat layer on top.
+///
+/// The lobes are evaluated in a local frame where `z` is the normal facing the viewer.
+pub struct Bsdf {
+    n: Vec3<F>,
+    t: Vec3<F>,
+    b: Vec3<F>,
+    /// The direction towards the viewer in the local frame.
+    v: Vec3<F>,
+
+    albedo: Vec3<F>,
+    roughness: F,
+    subsurface: F,
+    sheen: Vec3<F>,
+
+    /// F0 of the opaque dielectric specular, tinted by `specular_tint`.
+    specular: Vec3<F>,
+    specular_weight: F,
+    /// The reflectance of metals at grazing angles.
+    edge: Vec3<F>,
+    metallic: F,
+    alpha_x: F,
+    alpha_y: F,
+    thin_film: Option<(F, F)>,
+
+    /// The relative index of refraction, inside over the side of the viewer.
+    eta: F,
+    ior: F,
+    alpha_t: F,
+    /// The tint of transmitted light, applied when entering the material.
+    transmission_color: Vec3<F>,
+    thin_walled: bool,
+
+    coat: F,
+    coat_eta: F,
+    alpha_c: F,
+    /// The attenuation of the base layer by the coat.
+    base: Vec3<F>,
+
+    /// The physical weights of the diffuse and transmission lobes.
+    diffuse_w: F,
+    transmission_w: F,
+
+    /// The probabilities to sample the diffuse, specular, transmission and coat lobes.
+    p_diffuse: F,
+    p_specular: F,
+    p_transmission: F,
+    p_coat: F,
+}
+
+impl Bsdf {
+    /// Set up the BSDF for the viewer direction `v` (pointing away from the surface) and the
+    /// normal `n` facing the viewer. `inside` is true if the viewer is inside the material.
+    pub fn new(material: &Material, n: Vec3<F>, v: Vec3<F>, inside: bool) -> Self {
+        // Voxel faces are axis aligned, derive a stable tangent from the normal
+        let helper = if n.y.abs() < 0.9 {
+            Vec3::unit_y()
+        } else {
+            Vec3::unit_x()
+        };
+        let t = helper.cross(n).normalized();
+        let b = n.cross(t);
+        let (sin_r, cos_r) = (material.anisotropic_rot * F_TAU).sin_cos();
+        let (t, b) = (t * cos_r + b * sin_r, b * cos_r - t * sin_r);
+
+        let v = Vec3::new(v.dot(t), v.dot(b), v.dot(n).max(1e-4)).normalized();
+
+        let albedo = material.base_color_linear();
+        let lum = luminance(albedo);
+        let tint = if lum > 0.0 { albedo / lum } else { Vec3::one() };
+
+        let metallic = material.metallic.clamp(0.0, 1.0);
+        let transmission = material.transmission.clamp(0.0, 1.0);
+        let roughness = material.roughness.clamp(0.0, 1.0);
+
+        let aspect = (1.0 - 0.9 * material.anisotropic.clamp(0.0, 1.0)).sqrt();
+        let alpha = roughness * roughness;
+
+        let ior = material.ior.max(1.0);
+        let eta = if inside { 1.0 / ior } else { ior };
+
+        // The OpenPBR coat takes precedence over the Disney clearcoat if it is stronger
+        let (coat, coat_ior, alpha_c) = if material.coat_weight >= material.clearcoat {
+            let ior = if material.coat_ior > 1.0 {
+                material.coat_ior
+            } else {
+                1.5
+            };
+            (
+                material.coat_weight,
+                ior,
+                material.coat_roughness * material.coat_roughness,
+            )
+        } else {
+            let gloss = material.clearcoat_gloss.clamp(0.0, 1.0);
+            (material.clearcoat, 1.5, 0.1 + (0.001 - 0.1) * gloss)
+        };
+        // The coat is only seen from outside
+        let coat = if inside { 0.0 } else { coat.clamp(0.0, 1.0) };
+        let base = if inside {
+            Vec3::one()
+        } else {
+            let coat_weight = material.coat_weight.clamp(0.0, 1.0);
+            let coat_tint = Vec3::one() + (material.coat_color - Vec3::one()) * coat_weight;
+            coat_tint * (1.0 - coat * fresnel_dielectric(v.z, coat_ior))
+        };
+
+        let diffuse_w = if inside {
+            0.0
+        } else {
+            (1.0 - metallic) * (1.0 - transmission)
+        };
+        // Once inside, the ray can only leave through the dielectric interface
+        let transmission_w = if inside {
+            1.0
+        } else {
+            (1.0 - metallic) * transmission
+        };
+
+        let p_diffuse = diffuse_w;
+        let p_specular = if inside { 0.0 } else { 1.0 - transmission_w };
+        let p_transmission = transmission_w;
+        let p_coat = coat * 0.25;
+        let total = p_diffuse + p_specular + p_transmission + p_coat;
+        let total = if total > 0.0 { total } else { 1.0 };
+
+        let specular_tint = material.specular_tint.clamp(0.0, 1.0);
+        let specular = (Vec3::one() + (tint - Vec3::one()) * specular_tint)
+            * (material.specular.max(0.0) * 0.08);
+
+        let thin_film = match (material.thin_film_thickness, material.thin_film_ior) {
+            (Some(thickness), ior) if thickness > 0.0 => Some((thickness, ior.unwrap_or(1.33))),
+            _ => None,
+        };
+
+        Self {
+            n,
+            t,
+            b,
+            v,
+
+            albedo,
+            roughness,
+            subsurface: material.subsurface.clamp(0.0, 1.0),
+            sheen: (Vec3::one() + (tint - Vec3::one()) * material.sheen_tint.clamp(0.0, 1.0))
+                * material.sheen.max(0.0),
+
+            specular,
+            specular_weight: material.specular_weight.max(0.0),
+            edge: material.specular_edge_color.unwrap_or(Vec3::one()),
+            metallic,
+            alpha_x: (alpha / aspect).max(MIN_ALPHA),
+            alpha_y: (alpha * aspect).max(MIN_ALPHA),
+            thin_film,
+
+            eta,
+            ior,
+            // Index matched materials let transmitted rays pass straight through
+            alpha_t: if material.is_index_matched() {
+                0.0
+            } else {
+                material.transmission_roughness.clamp(0.0, 1.0).powi(2)
+            },
+            thin_walled: material.thin_walled,
+            // With a volume the color comes from the absorption inside
+            transmission_color: if inside || material.has_volume() {
+                Vec3::one()
+            } else {
+                albedo
+            },
+
+            coat,
+            coat_eta: coat_ior,
+            alpha_c: alpha_c.max(MIN_ALPHA),
+            base,
+
+            diffuse_w,
+            transmission_w,
+
+            p_diffuse: p_diffuse / total,
+            p_specular: p_specular / total,
+            p_transmission: p_transmission / total,
+            p_coat: p_coat / total,
+        }
+    }
+
+    /// The BSDF times the cosine for the world space direction `l`, without the smooth
+    /// transmission lobe.
+    pub fn eval(&self, l: Vec3<F>) -> Vec3<F> {
+        self.eval_local(self.to_local(l)).0
+    }
+
+    /// The solid angle pdf of `sample` for the world space direction `l`.
+    pub fn pdf(&self, l: Vec3<F>) -> F {
+        self.eval_local(self.to_local(l)).1
+    }
+
+    /// Sample a direction, the lobe is picked proportional to its sampling probability.
+    /// `renderer` provides the refraction.
+    pub fn sample<R: Rng>(&self, renderer: &dyn Renderer, rng: &mut R) -> Option<BsdfSample> {
+        let v = self.v;
+        let mut u: F = rng.random();
+        let rnd = (rng.random::<F>(), rng.random::<F>());
+
+        let diffuse = u < self.p_diffuse;
+        let l = if diffuse {
+            // Cosine weighted hemisphere
+            let r = rnd.0.sqrt();
+            let phi = F_TAU * rnd.1;
+            Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - rnd.0).max(0.0).sqrt())
+        } else if u < self.p_diffuse + self.p_specular {
+            let h = sample_vndf(v, self.alpha_x, self.alpha_y, rnd);
+            reflect(v, h)
+        } else if u < self.p_diffuse + self.p_specular + self.p_transmission {
+            u = (u - self.p_diffuse - self.p_specular) / self.p_transmission;
+
+            if self.alpha_t <= SMOOTH_ALPHA {
+                return self.sample_smooth(renderer, u);
+            }
+
+            let h = sample_vndf(v, self.alpha_t, self.alpha_t, rnd);
+            let fresnel = fresnel_dielectric(v.dot(h), self.eta);
+            if u < fresnel {
+                reflect(v, h)
+            } else if self.thin_walled {
+                let l = reflect(v, h);
+                Vec3::new(l.x, l.y, -l.z)
+            } else {
+                renderer.refract(-v, h, 1.0 / self.eta)?
+            }
+        } else {
+            let h = sample_vndf(v, self.alpha_c, self.alpha_c, rnd);
+            reflect(v, h)
+        };
+
+        let (f, pdf) = self.eval_local(l);
+        if pdf <= 0.0 || f == Vec3::zero() {
+            return None;
+        }
+
+        let scatter = if diffuse {
+            Scatter::Diffuse
+        } else if l.z < 0.0 {
+            Scatter::Transmission
+        } else {
+            Scatter::Glossy
+        };
+
+        Some(BsdfSample {
+            direction: self.to_world(l),
+            weight: f / pdf,
+            pdf: Some(pdf),
+            scatter,
+        })
+    }
+
+    /// Sample the smooth dielectric: a mirror reflection or a refraction, chosen by the
+    /// Fresnel reflectance.
+    fn sample_smooth(&self, renderer: &dyn Renderer, u: F) -> Option<BsdfSample> {
+        let v = self.v;
+        let weight = self.base * (self.transmission_w / self.p_transmission);
+
+        let fresnel = fresnel_dielectric(v.z, self.eta);
+        let (l, weight) = if u < fresnel {
+            (Vec3::new(-v.x, -v.y, v.z), weight)
+        } else if self.thin_walled {
+            (-v, weight * self.transmission_color)
+        } else {
+            let l = renderer.refract(-v, Vec3::unit_z(), 1.0 / self.eta)?;
+            // Radiance is compressed into the denser medium
+            (l, weight * self.transmission_color / (self.eta * self.eta))
+        };
+
+        Some(BsdfSample {
+            direction: self.to_world(l),
+            weight,
+            pdf: None,
+            scatter: if l.z < 0.0 {
+                Scatter::Transmission
+            } else {
+                Scatter::Glossy
+            },
+        })
+    }
+
+    /// The BSDF times the absolute cosine and the pdf of the rough lobes for the local
+    /// direction `l`.
+    fn eval_local(&self, l: Vec3<F>) -> (Vec3<F>, F) {
+        let v = self.v;
+        let nv = v.z;
+        let mut f = Vec3::zero();
+        let mut pdf = 0.0;
+
+        if l.z > 0.0 {
+            let h = (v + l).normalized();
+            let nl = l.z;
+            let lh = l.dot(h);
+
+            if self.p_diffuse > 0.0 {
+                let fl = (1.0 - nl).powi(5);
+                let fv = (1.0 - nv).powi(5);
+
+                // Burley diffuse with retro-reflection
+                let fd90 = 0.5 + 2.0 * self.roughness * lh * lh;
+                let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+
+                // Hanrahan-Krueger approximation of subsurface scattering
+                let fss90 = lh * lh * self.roughness;
+                let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+                let ss = 1.25 * (fss * (1.0 / (nl + nv) - 0.5) + 0.5);
+
+                let diffuse = self.albedo * ((fd + (ss - fd) * self.subsurface) / F_PI)
+                    + self.sheen * (1.0 - lh).powi(5);
+                f += diffuse * self.base * (self.diffuse_w * nl);
+                pdf += self.p_diffuse * nl / F_PI;
+            }
+
+            if self.p_specular > 0.0 {
+                let d = ggx_d(h, self.alpha_x, self.alpha_y);
+                let g = smith_g(v, l, self.alpha_x, self.alpha_y);
+                f += self.specular_fresnel(lh) * self.base * (d * g / (4.0 * nv));
+                pdf += self.p_specular * smith_g1(v, self.alpha_x, self.alpha_y) * d / (4.0 * nv);
+            }
+
+            if self.p_transmission > 0.0 && self.alpha_t > SMOOTH_ALPHA {
+                let a = self.alpha_t;
+                let d = ggx_d(h, a, a);
+                let fresnel = fresnel_dielectric(v.dot(h), self.eta);
+                f += self.base
+                    * (self.transmission_w * fresnel * d * smith_g(v, l, a, a) / (4.0 * nv));
+                pdf += self.p_transmission * fresnel * smith_g1(v, a, a) * d / (4.0 * nv);
+            }
+
+            if self.p_coat > 0.0 {
+                let a = self.alpha_c;
+                let d = ggx_d(h, a, a);
+                let fresnel = fresnel_dielectric(lh, self.coat_eta);
+                f += Vec3::broadcast(self.coat * fresnel * d * smith_g(v, l, a, a) / (4.0 * nv));
+                pdf += self.p_coat * smith_g1(v, a, a) * d / (4.0 * nv);
+            }
+        } else if l.z < 0.0 && self.p_transmission > 0.0 && self.alpha_t > SMOOTH_ALPHA {
+            let a = self.alpha_t;
+
+            if self.thin_walled {
+                // Thin sheets transmit the mirrored reflection lobe without bending the ray
+                let mirrored = Vec3::new(l.x, l.y, -l.z);
+                let h = (v + mirrored).normalized();
+                let d = ggx_d(h, a, a);
+                let fresnel = fresnel_dielectric(v.dot(h), self.eta);
+                let g = smith_g(v, mirrored, a, a);
+                f += self.transmission_color
+                    * self.base
+                    * (self.transmission_w * (1.0 - fresnel) * d * g / (4.0 * nv));
+                pdf += self.p_transmission * (1.0 - fresnel) * smith_g1(v, a, a) * d / (4.0 * nv);
+            } else {
+                // The generalized half vector of the refraction (Walter et al.)
+                let mut h = -(v + l * self.eta).normalized();
+                if h.z < 0.0 {
+                    h = -h;
+                }
+                let vh = v.dot(h);
+                let lh = l.dot(h);
+                if vh > 0.0 && lh < 0.0 {
+                    let d = ggx_d(h, a, a);
+                    let g = smith_g(v, l, a, a);
+                    let fresnel = fresnel_dielectric(vh, self.eta);
+                    let denom = (vh + self.eta * lh).powi(2);
+
+                    let value = (1.0 - fresnel) * d * g * vh * -lh / (nv * denom);
+                    f += self.transmission_color
+                        * self.base
+                        * (self.transmission_w * value / (self.eta * self.eta));
+                    pdf += self.p_transmission
+                        * (1.0 - fresnel)
+                        * (smith_g1(v, a, a) * vh * d / nv)
+                        * (self.eta * self.eta * -lh / denom);
+                }
+            }
+        }
+
+        (f, pdf)
+    }
+
+    /// The Fresnel reflectance of the opaque specular lobe, the dielectric and metallic
+    /// parts blended by `metallic`.
+    fn specular_fresnel(&self, cos: F) -> Vec3<F> {
+        let schlick = (1.0 - cos).clamp(0.0, 1.0).powi(5);
+        let opaque = self.diffuse_w;
+
+        let (dielectric, metal) = match self.thin_film {
+            Some((thickness, film_ior)) => {
+                let dielectric = thin_film(cos, thickness, film_ior, Vec3::broadcast(self.ior));
+                // The equivalent index of refraction of the metal from its reflectance
+                let metal_ior = self.albedo.map(|f0| {
+                    let r = f0.clamp(0.0, 0.99).sqrt();
+                    (1.0 + r) / (1.0 - r)
+                });
+                (dielectric, thin_film(cos, thickness, film_ior, metal_ior))
+            }
+            None => (
+                self.specular + (Vec3::one() - self.specular) * schlick,
+                self.albedo + (self.edge - self.albedo) * schlick,
+            ),
+        };
+
+        dielectric * (opaque * self.specular_weight) + metal * self.metallic
+    }
+
+    fn to_local(&self, d: Vec3<F>) -> Vec3<F> {
+        Vec3::new(d.dot(self.t), d.dot(self.b), d.dot(self.n))
+    }
+
+    fn to_world(&self, d: Vec3<F>) -> Vec3<F> {
+        (self.t * d.x + self.b * d.y + self.n * d.z).normalized()
+    }
+}
+
+/// Mirror `v` (pointing away from the surface) at the microfacet normal `h`.
+fn reflect(v: Vec3<F>, h: Vec3<F>) -> Vec3<F> {
+    h * (2.0 * v.dot(h)) - v
+}
+
+/// The Fresnel reflectance of a dielectric interface for unpolarized light, `eta` is the
+/// index of refraction of the far side over the near side.
+fn fresnel_dielectric(cos_i: F, eta: F) -> F {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// The reflectance of a thin film (thickness in nanometers) on a substrate for the red,
+/// green and blue wavelengths, from the interference of the two interface reflections.
+fn thin_film(cos_i: F, thickness: F, film_ior: F, substrate_ior: Vec3<F>) -> Vec3<F> {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_i = 1.0 - cos_i * cos_i;
+
+    let sin2_f = sin2_i / (film_ior * film_ior);
+    if sin2_f >= 1.0 {
+        return Vec3::one();
+    }
+    let cos_f = (1.0 - sin2_f).sqrt();
+
+    // Amplitude reflection coefficients (s, p) of an interface
+    let interface = |n1: F, cos1: F, n2: F, cos2: F| {
+        (
+            (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2),
+            (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2),
+        )
+    };
+    let r12 = interface(1.0, cos_i, film_ior, cos_f);
+
+    let wavelengths = Vec3::new(650.0, 550.0, 450.0);
+    Vec3::<usize>::new(0, 1, 2).map(|c| {
+        let n3 = substrate_ior[c];
+        let sin2_s = sin2_i / (n3 * n3);
+        let cos_s = (1.0 - sin2_s).max(0.0).sqrt();
+        let r23 = interface(film_ior, cos_f, n3, cos_s);
+
+        let phase = 2.0 * F_TAU * film_ior * thickness * cos_f / wavelengths[c];
+        let airy = |a: F, b: F| {
+            let cross = 2.0 * a * b * phase.cos();
+            ((a * a + b * b + cross) / (1.0 + a * a * b * b + cross)).clamp(0.0, 1.0)
+        };
+        0.5 * (airy(r12.0, r23.0) + airy(r12.1, r23.1))
+    })
+}
+
+/// The anisotropic GGX normal distribution.
+fn ggx_d(h: Vec3<F>, ax: F, ay: F) -> F {
+    if h.z <= 0.0 {
+        return 0.0;
+    }
+    let e = (h.x / ax).powi(2) + (h.y / ay).powi(2) + h.z * h.z;
+    1.0 / (F_PI * ax * ay * e * e)
+}
+
+fn smith_lambda(w: Vec3<F>, ax: F, ay: F) -> F {
+    let z2 = w.z * w.z;
+    if z2 <= 0.0 {
+        return F::MAX;
+    }
+    let a2 = (ax * w.x).powi(2) + (ay * w.y).powi(2);
+    0.5 * ((1.0 + a2 / z2).sqrt() - 1.0)
+}
+
+fn smith_g1(w: Vec3<F>, ax: F, ay: F) -> F {
+    1.0 / (1.0 + smith_lambda(w, ax, ay))
+}
+
+/// The height correlated Smith masking-shadowing term.
+fn smith_g(v: Vec3<F>, l: Vec3<F>, ax: F, ay: F) -> F {
+    1.0 / (1.0 + smith_lambda(v, ax, ay) + smith_lambda(l, ax, ay))
+}
+
+/// Sample a microfacet normal from the distribution of visible normals (Heitz 2018).
+fn sample_vndf(v: Vec3<F>, ax: F, ay: F, rnd: (F, F)) -> Vec3<F> {
+    let vh = Vec3::new(ax * v.x, ay * v.y, v.z).normalized();
+
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+    } else {
+        Vec3::unit_x()
+    };
+    let t2 = vh.cross(t1);
+
+    let r = rnd.0.sqrt();
+    let phi = F_TAU * rnd.1;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    Vec3::new(ax * nh.x, ay * nh.y, nh.z.max(1e-6)).normalized()
+}
@@ -1,10 +1,23 @@
 use crate::prelude::*;
 use vek::{Vec2, Vec3, Vec4};
 
-use rand::Rng;
+/// The width of the edge outlines, in voxels.
+const OUTLINE_WIDTH: F = 0.06;
 
+/// How much the outlines darken the voxel color.
+const OUTLINE_DARKEN: F = 0.45;
+
+/// A fast deterministic renderer for editing: one ray per pixel, lambert shading from the
+/// sun of the scene, ambient occlusion from the neighbouring voxels of the hit face and
+/// optional outlines along the visible voxel edges. No shadows and no bounces, so the frame
+/// is final after a single sample.
+#[derive(Clone)]
 pub struct EditShader {
-    pub background_color: Vec3<F>,
+    /// The share of the light which is ambient, scaled by the ambient occlusion.
+    pub ambient: F,
+    /// How much the neighbouring voxels darken the ambient light, 0 disables the occlusion.
+    pub ao: F,
+    pub outlines: bool,
 }
 
 impl Renderer for EditShader {
@@ -13,7 +26,9 @@ impl Renderer for EditShader {
         Self: Sized,
     {
         Self {
-            background_color: Vec3::broadcast(0.2),
+            ambient: 0.35,
+            ao: 1.0,
+            outlines: false,
         }
     }
 
@@ -21,14 +36,47 @@ impl Renderer for EditShader {
         "EditShader"
     }
 
-    /// Get the background color.
-    fn background_color(&mut self) -> Vec3<F> {
-        self.background_color
+    fn clone_box(&self) -> Box<dyn Renderer> {
+        Box::new(self.clone())
+    }
+
+    fn params(&self) -> Vec<RendererParam> {
+        vec![
+            RendererParam::Float(
+                "ambient".into(),
+                "Ambient".into(),
+                "The share of the light which is ambient.".into(),
+                self.ambient,
+                0.0..=1.0,
+            ),
+            RendererParam::Float(
+                "ao".into(),
+                "AO".into(),
+                "The strength of the ambient occlusion.".into(),
+                self.ao,
+                0.0..=1.0,
+            ),
+            RendererParam::Selector(
+                "outlines".into(),
+                "Outlines".into(),
+                "Outline the visible voxel edges.".into(),
+                vec!["Off".into(), "On".into()],
+                self.outlines as i32,
+            ),
+        ]
     }
 
-    /// Set the background color.
-    fn set_background_color(&mut self, color: Vec3<F>) {
-        self.background_color = color;
+    fn set_param(&mut self, id: &str, value: F) {
+        match id {
+            "ambient" => self.ambient = value.clamp(0.0, 1.0),
+            "ao" => self.ao = value.clamp(0.0, 1.0),
+            "outlines" => self.outlines = value >= 0.5,
+            _ => {}
+        }
+    }
+
+    fn progressive(&self) -> bool {
+        false
     }
 
     /// Render the pixel at the given screen position.
@@ -38,65 +86,128 @@ impl Renderer for EditShader {
         resolution: Vec2<F>,
         grid: &VoxelGrid,
         palette: &Palette,
-        camera: &Box<dyn Camera>,
+        scene: &Scene,
+        camera: &dyn Camera,
+        _settings: &RenderSettings,
+        _sample: u32,
+        aov: &mut AovSample,
     ) -> Vec4<F> {
-        let mut rng = rand::rng();
+        // Always through the pixel center
+        let ray = camera.create_ray(uv, resolution, Vec2::broadcast(0.5));
+        let hit = grid.dda(&ray);
+        *aov = AovSample::from_hit(&ray, &hit, palette);
 
-        // let mut acc = Vec3::<F>::zero();
-        // let mut mask = Vec3::<F>::one();
+        let HitType::Voxel(m) = hit.hit else {
+            let mut c = self.background(scene).radiance(ray.dir);
+            // Brighten the grid bounds
+            if matches!(hit.hit, HitType::BBox(_)) {
+                c += Vec3::broadcast(0.2);
+            }
+            return Vec4::new(c.x, c.y, c.z, 1.0);
+        };
+
+        // The outward normal of the hit face
+        let n = if hit.normal.dot(ray.dir) < 0.0 {
+            hit.normal
+        } else {
+            -hit.normal
+        };
+        if n == Vec3::zero() {
+            return Vec4::new(0.0, 0.0, 0.0, 1.0);
+        }
 
-        let ray = camera.create_ray(uv, resolution, Vec2::new(rng.random(), rng.random()));
+        let material = palette.get(m);
+        let (light_dir, light_color) = self.light(scene);
 
-        // -------------- voxel DDA -------------------------------------------
-        let hit = grid.dda(&ray);
-        match hit.hit {
-            HitType::Outside => {
-                // background
-                let c = self.background_color;
-                Vec4::new(c.x, c.y, c.z, 1.0)
+        let p = hit.hitpoint * grid.density_f;
+        let voxel = (p - n * 0.5).map(|v| v.floor() as i32);
+        let normal = n.map(|v| v.round() as i32);
+
+        let (ao, edge) = self.face(grid, voxel, normal, p - voxel.map(|v| v as F));
+
+        let ao = 1.0 - self.ao * (1.0 - ao);
+        let diffuse = n.dot(light_dir).max(0.0);
+        let mut color = material.base_color_linear()
+            * (light_color * diffuse * (1.0 - self.ambient) + self.ambient * ao)
+            + material.emission_color * material.emission_strength;
+
+        if self.outlines && edge < OUTLINE_WIDTH {
+            color *= 1.0 - OUTLINE_DARKEN;
+        }
+
+        Vec4::new(color.x, color.y, color.z, 1.0)
+    }
+}
+
+impl EditShader {
+    /// The direction towards and the color of the key light: the first directional light
+    /// of the scene, the sun of the background, or a fixed light from the upper left.
+    fn light(&self, scene: &Scene) -> (Vec3<F>, Vec3<F>) {
+        let directional = |light: &Light| match light.light_type {
+            LightType::Directional { direction, .. } => {
+                let color = light.color * light.intensity;
+                Some((
+                    direction.normalized(),
+                    color / color.reduce_partial_max().max(1.0),
+                ))
             }
-            HitType::BBox(_) => {
-                // background
-                let c = self.background_color;
-                Vec4::new(c.x, c.y, c.z, 1.0)
+            _ => None,
+        };
+
+        scene
+            .lights
+            .iter()
+            .find_map(directional)
+            .or_else(|| self.background(scene).sun().as_ref().and_then(directional))
+            .unwrap_or((Vec3::new(-0.5, 1.0, -0.5).normalized(), Vec3::one()))
+    }
+
+    /// The ambient occlusion and the distance to the nearest visible edge of the face of
+    /// `voxel` facing `normal`, at the position `f` inside the voxel (0..1 on every axis).
+    ///
+    /// The occlusion is the classic per corner term from the two side and the corner voxels
+    /// in front of the face, interpolated over the face. An edge is visible if the voxel
+    /// beyond it is empty (a silhouette) or the voxel in front of it is set (a crease).
+    fn face(&self, grid: &VoxelGrid, voxel: Vec3<i32>, normal: Vec3<i32>, f: Vec3<F>) -> (F, F) {
+        // The two axes spanning the face
+        let axis = if normal.x != 0 {
+            0
+        } else if normal.y != 0 {
+            1
+        } else {
+            2
+        };
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut u = Vec3::zero();
+        u[a] = 1;
+        let mut v = Vec3::zero();
+        v[b] = 1;
+
+        let set = |p: Vec3<i32>| grid.get_voxel(p).is_some();
+        let front = voxel + normal;
+
+        let corner = |du: i32, dv: i32| -> F {
+            let side1 = set(front + u * du);
+            let side2 = set(front + v * dv);
+            if side1 && side2 {
+                return 0.0;
             }
-            HitType::Voxel(m) => {
-                // -------------- palette lookup --------------------------------------
-                let mat = palette.get(m);
-                let mut color = mat.base_color; // linear 0-1
-
-                // -------------- basic diffuse lighting ------------------------------
-                let light_dir = Vec3::new(-0.5, 1.0, -0.5).normalized();
-                let n = hit.normal.normalized();
-                let diff = n.dot(light_dir).max(0.0);
-                let mut shade = 0.20 /*ambient*/ + 0.80 * diff;
-
-                // -------------- tiny edge/specular accent ---------------------------
-                let view_dir = -ray.dir; // pointing to camera
-                let spec_boost = view_dir.dot(n).max(0.0).powf(20.0);
-                shade += 0.15 * spec_boost; // up to +15 %
-
-                // -------------- 6-tap ambient-occlusion -----------------------------
-                let p = hit.hit_point_local; // i32 voxel coords
-                let neigh = [
-                    Vec3::new(1, 0, 0),
-                    Vec3::new(-1, 0, 0),
-                    Vec3::new(0, 1, 0),
-                    Vec3::new(0, -1, 0),
-                    Vec3::new(0, 0, 1),
-                    Vec3::new(0, 0, -1),
-                ];
-                let empty = neigh
-                    .iter()
-                    .filter(|&&d| grid.get(p.x + d.x, p.y + d.y, p.z + d.z).is_none())
-                    .count() as F; // 0‥6
-                let ao = 1.0 + empty * 0.09; // up to +54 %
-                shade *= ao;
-
-                // -------------- final colour ----------------------------------------
-                color *= shade.clamp(0.0, 1.5); // keep sane
-                Vec4::new(color.x, color.y, color.z, 1.0)
+            let corner = set(front + u * du + v * dv);
+            (3 - side1 as i32 - side2 as i32 - corner as i32) as F / 3.0
+        };
+
+        let (fu, fv) = (f[a].clamp(0.0, 1.0), f[b].clamp(0.0, 1.0));
+        let ao = (corner(-1, -1) * (1.0 - fu) + corner(1, -1) * fu) * (1.0 - fv)
+            + (corner(-1, 1) * (1.0 - fu) + corner(1, 1) * fu) * fv;
+
+        let visible = |d: Vec3<i32>| !set(voxel + d) || set(front + d);
+        let mut edge = F::MAX;
+        for (d, distance) in [(-u, fu), (u, 1.0 - fu), (-v, fv), (v, 1.0 - fv)] {
+            if visible(d) {
+                edge = edge.min(distance);
             }
         }
+
+        (ao, edge)
     }
 }
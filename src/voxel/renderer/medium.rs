@@ -0,0 +1,134 @@
+use crate::prelude::*;
+use crate::{F_PI, F_TAU};
+use vek::Vec3;
+
+/// The homogeneous participating medium inside the transmissive voxels of a material.
+///
+/// The coefficients are per world unit (one tile). `transmission_depth` is the distance at
+/// which light has taken on the absorption color, which defaults to the base color.
+/// `volume_scatter_color` is the scattering coefficient per depth and `volume_anisotropy`
+/// the Henyey-Greenstein asymmetry.
+pub struct Medium {
+    pub absorption: Vec3<F>,
+    pub scattering: Vec3<F>,
+    pub anisotropy: F,
+}
+
+/// The result of sampling a free-flight distance through the medium.
+pub struct MediumSample {
+    /// The distance travelled, the maximum distance if the ray passed through.
+    pub distance: F,
+    /// The throughput of the segment divided by its pdf.
+    pub weight: Vec3<F>,
+    /// True if the ray scatters at `distance`.
+    pub scattered: bool,
+}
+
+impl Medium {
+    /// The medium of a material, None if it has no volume.
+    pub fn from_material(material: &Material) -> Option<Self> {
+        if !material.has_volume() {
+            return None;
+        }
+
+        let depth = material.transmission_depth.unwrap_or(1.0).max(1e-4);
+        let color = material
+            .volume_absorption_color
+            .unwrap_or_else(|| material.base_color_linear());
+
+        Some(Self {
+            absorption: color.map(|c| -c.clamp(1e-4, 1.0).ln() / depth),
+            scattering: material
+                .volume_scatter_color
+                .unwrap_or(Vec3::zero())
+                .map(|c| c.max(0.0) / depth),
+            anisotropy: material.volume_anisotropy.unwrap_or(0.0).clamp(-0.99, 0.99),
+        })
+    }
+
+    pub fn extinction(&self) -> Vec3<F> {
+        self.absorption + self.scattering
+    }
+
+    /// Beer-Lambert transmittance over the distance.
+    pub fn transmittance(&self, distance: F) -> Vec3<F> {
+        self.extinction().map(|s| (-s * distance).exp())
+    }
+
+    /// Sample the distance to the next scattering event along a segment of `max_distance`.
+    /// The channel used for the exponential sampling is picked with `rnd.0`, the weights
+    /// account for all three (spectral MIS).
+    pub fn sample_distance(&self, max_distance: F, rnd: (F, F)) -> MediumSample {
+        let extinction = self.extinction();
+        let channel = ((rnd.0 * 3.0) as usize).min(2);
+        let sigma = extinction[channel];
+
+        let distance = if sigma > 0.0 {
+            -(1.0 - rnd.1).ln() / sigma
+        } else {
+            F::MAX
+        };
+
+        if distance < max_distance {
+            let transmittance = self.transmittance(distance);
+            let pdf = (extinction * transmittance).sum() / 3.0;
+            MediumSample {
+                distance,
+                weight: if pdf > 0.0 {
+                    transmittance * self.scattering / pdf
+                } else {
+                    Vec3::zero()
+                },
+                scattered: true,
+            }
+        } else {
+            let transmittance = self.transmittance(max_distance);
+            let pdf = transmittance.sum() / 3.0;
+            MediumSample {
+                distance: max_distance,
+                weight: if pdf > 0.0 {
+                    transmittance / pdf
+                } else {
+                    Vec3::zero()
+                },
+                scattered: false,
+            }
+        }
+    }
+
+    /// The Henyey-Greenstein phase function for the cosine between the propagation
+    /// direction and the scattered direction.
+    pub fn phase(&self, cos: F) -> F {
+        henyey_greenstein(cos, self.anisotropy)
+    }
+
+    /// Sample a scattered direction for the propagation direction `dir`, the pdf equals the
+    /// phase function.
+    pub fn sample_phase(&self, dir: Vec3<F>, rnd: (F, F)) -> Vec3<F> {
+        let g = self.anisotropy;
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * rnd.0
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * rnd.0);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = F_TAU * rnd.1;
+
+        let w = dir.normalized();
+        let helper = if w.x.abs() > 0.9 {
+            Vec3::unit_y()
+        } else {
+            Vec3::unit_x()
+        };
+        let u = helper.cross(w).normalized();
+        let v = w.cross(u);
+        (u * (sin * phi.cos()) + v * (sin * phi.sin()) + w * cos).normalized()
+    }
+}
+
+/// The Henyey-Greenstein phase function with the asymmetry `g`.
+pub fn henyey_greenstein(cos: F, g: F) -> F {
+    let denom = 1.0 + g * g - 2.0 * g * cos;
+    (1.0 - g * g) / (4.0 * F_PI * denom * denom.sqrt())
+}
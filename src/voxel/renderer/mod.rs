@@ -1,60 +1,90 @@
-// pub mod editshader;
+pub mod bsdf;
+pub mod editshader;
+pub mod medium;
 pub mod pbr;
+pub mod registry;
+pub mod service;
+pub mod settings;
 
 use crate::prelude::*;
+use crate::voxel::color;
+use rand::Rng;
+use std::ops::RangeInclusive;
 use vek::{Vec2, Vec3, Vec4};
 
+/// A parameter of a renderer, shown in the tool params of the model view.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RendererParam {
+    /// Id, Name, Status, Value, Range
+    Float(String, String, String, F, RangeInclusive<F>),
+    /// Id, Name, Status, Value, Range
+    Int(String, String, String, i32, RangeInclusive<i32>),
+    /// Id, Name, Status, Options, Value
+    Selector(String, String, String, Vec<String>, i32),
+}
+
 #[allow(unused)]
 pub trait Renderer: Send + Sync {
     fn new() -> Self
     where
         Self: Sized;
 
-    /// Returns the name of the renderer.
+    /// Returns the name of the renderer, renderers are registered by it.
     fn name(&self) -> &str;
 
-    /// Render the pixel at the given screen position.
+    /// Returns a boxed copy of the renderer, parameter changes are applied to a copy while
+    /// the render workers keep the old one.
+    fn clone_box(&self) -> Box<dyn Renderer>;
+
+    /// The parameters of the renderer.
+    fn params(&self) -> Vec<RendererParam> {
+        vec![]
+    }
+
+    /// Set the parameter with the given id, ints and selector indices are passed as floats.
+    fn set_param(&mut self, id: &str, value: F) {}
+
+    /// Whether the samples of a pixel differ and get accumulated, a renderer which is final
+    /// after one sample returns false and is only rendered once per frame.
+    fn progressive(&self) -> bool {
+        true
+    }
+
+    /// Render the pixel at the given screen position. `sample` is the index of the sample
+    /// of the pixel, together with the seed of the settings it selects the random numbers.
+    /// The AOV passes of the first hit of the camera ray are written to `aov`.
+    #[allow(clippy::too_many_arguments)]
     fn render(
         &self,
         uv: Vec2<F>,
         resolution: Vec2<F>,
         grid: &VoxelGrid,
         palette: &Palette,
-        camera: &Box<dyn Camera>,
+        scene: &Scene,
+        camera: &dyn Camera,
+        settings: &RenderSettings,
+        sample: u32,
+        aov: &mut AovSample,
     ) -> Vec4<F> {
         Vec4::zero()
     }
 
-    /// Get the background color.
-    fn background_color(&mut self) -> Vec3<F> {
-        Vec3::zero()
+    /// The background provider queried for rays leaving the grid, the scene background by
+    /// default.
+    fn background<'a>(&self, scene: &'a Scene) -> &'a dyn BackgroundProvider {
+        &scene.background
     }
 
-    /// Set the background color.
-    fn set_background_color(&mut self, color: Vec3<F>) {}
-
     /// Converts an sRGB Vec3 to linear space.
     #[inline(always)]
     fn srgb_to_linear(&self, v: Vec3<F>) -> Vec3<F> {
-        v.map(|c| {
-            if c <= 0.04045 {
-                c / 12.92
-            } else {
-                ((c + 0.055) / 1.055).powf(2.4)
-            }
-        })
+        color::srgb_to_linear(v)
     }
 
     /// Converts a linear Vec3 to sRGB space.
     #[inline(always)]
     fn linear_to_srgb(&self, v: Vec3<F>) -> Vec3<F> {
-        v.map(|c| {
-            if c <= 0.0031308 {
-                c * 12.92
-            } else {
-                1.055 * c.powf(1.0 / 2.4) - 0.055
-            }
-        })
+        color::linear_to_srgb(v)
     }
 
     /// Reflects vector `v` about normal `n`.
@@ -85,3 +115,9 @@ pub trait Renderer: Send + Sync {
         Vec3::broadcast(0.2 + check * 0.3)
     }
 }
+
+impl Clone for Box<dyn Renderer> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
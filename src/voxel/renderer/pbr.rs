@@ -1,20 +1,19 @@
-use crate::F_PI;
 use crate::prelude::*;
+use crate::voxel::renderer::bsdf::Bsdf;
+use crate::voxel::renderer::medium::Medium;
+use crate::voxel::renderer::settings::{Bounces, Scatter};
 use vek::{Vec2, Vec3, Vec4};
 
 use rand::Rng;
 
-#[allow(dead_code)]
-#[allow(clippy::upper_case_acronyms)]
-enum ReflectionType {
-    Diffuse,
-    Specular,
-    Refractive,
-    GGX,
-}
+/// How far secondary rays start above the surface, in voxels. The tile DDA steps back a
+/// hundredth of a voxel, so this has to be larger.
+const SURFACE_OFFSET: F = 0.05;
 
+#[derive(Clone)]
 pub struct PBR {
-    pub background_color: Vec3<F>,
+    /// The exposure of the image in stops.
+    pub exposure: F,
 }
 
 impl Renderer for PBR {
@@ -22,23 +21,31 @@ impl Renderer for PBR {
     where
         Self: Sized,
     {
-        Self {
-            background_color: Vec3::broadcast(0.2),
-        }
+        Self { exposure: 0.0 }
     }
 
     fn name(&self) -> &str {
         "PBR"
     }
 
-    /// Get the background color.
-    fn background_color(&mut self) -> Vec3<F> {
-        self.background_color
+    fn clone_box(&self) -> Box<dyn Renderer> {
+        Box::new(self.clone())
+    }
+
+    fn params(&self) -> Vec<RendererParam> {
+        vec![RendererParam::Float(
+            "exposure".into(),
+            "Exposure".into(),
+            "The exposure of the image in stops.".into(),
+            self.exposure,
+            -8.0..=8.0,
+        )]
     }
 
-    /// Set the background color.
-    fn set_background_color(&mut self, color: Vec3<F>) {
-        self.background_color = color;
+    fn set_param(&mut self, id: &str, value: F) {
+        if id == "exposure" {
+            self.exposure = value;
+        }
     }
 
     /// Render the pixel at the given screen position.
@@ -49,228 +56,483 @@ impl Renderer for PBR {
         // project: Arc<Project>,
         grid: &VoxelGrid,
         palette: &Palette,
-        camera: &Box<dyn Camera>,
+        scene: &Scene,
+        camera: &dyn Camera,
+        settings: &RenderSettings,
+        sample: u32,
+        aov: &mut AovSample,
     ) -> Vec4<F> {
-        let mut rng = rand::rng();
+        let mut rng = settings.rng(uv, resolution, sample);
 
         let mut acc = Vec3::<F>::zero();
         let mut mask = Vec3::<F>::one();
+        // The pdf of the last BSDF sample, None for camera rays and delta reflections
+        let mut bsdf_pdf: Option<F> = None;
 
         let mut ray = camera.create_ray(uv, resolution, Vec2::new(rng.random(), rng.random()));
 
-        // let hit = grid.dda(&ray);
+        // The material of the transmissive voxels the ray currently travels through
+        let mut medium: Option<u8> = None;
+        // The last scattering vertex, the reference point of the light pdfs for MIS
+        let mut vertex = ray.origin;
+
+        let mut bounces = Bounces::default();
+        let mut camera_ray = true;
+        // Light gathered after the first bounce is clamped against fireflies
+        let clamp = |radiance: Vec3<F>, bounces: &Bounces| {
+            if bounces.total > 0 {
+                settings.clamp(radiance)
+            } else {
+                radiance
+            }
+        };
+
+        loop {
+            let hit = match medium {
+                Some(material) => grid.exit(&ray, material),
+                None => grid.dda(&ray),
+            };
+            if camera_ray {
+                *aov = AovSample::from_hit(&ray, &hit, palette);
+                camera_ray = false;
+            }
+
+            // Light scattered by the fog between the last vertex and the hit
+            if medium.is_none()
+                && let Some(fog) = &scene.fog
+            {
+                let distance = match hit.hit {
+                    HitType::Voxel(_) => (hit.hitpoint - ray.origin).magnitude(),
+                    _ => F::MAX,
+                };
+                let (transmittance, inscattered) = self.march_fog(
+                    scene, grid, palette, settings, fog, &ray, distance, &mut rng,
+                );
+                acc += clamp(mask * inscattered, &bounces);
+                mask *= transmittance;
+            }
+
+            // Free flight through the volume of the medium
+            if let Some(m) = medium
+                && let Some(volume) = Medium::from_material(palette.get(m))
+            {
+                let max_distance = (hit.hitpoint - ray.origin).magnitude();
+                let flight = volume.sample_distance(max_distance, (rng.random(), rng.random()));
+                mask *= flight.weight;
+
+                if flight.scattered {
+                    let p = ray.at(flight.distance);
+                    let dir = ray.dir;
+                    let phase = |l: Vec3<F>| volume.phase(dir.dot(l));
+
+                    let light = self.sample_lights(
+                        scene,
+                        grid,
+                        palette,
+                        p,
+                        medium,
+                        &mut rng,
+                        |l| Vec3::broadcast(phase(l)),
+                        phase,
+                    );
+                    acc += clamp(mask * light, &bounces);
+
+                    if !bounces.add(Scatter::Volume, settings)
+                        || !self.roulette(settings, &bounces, &mut mask, &mut rng)
+                    {
+                        break;
+                    }
+
+                    let scattered = volume.sample_phase(dir, (rng.random(), rng.random()));
+                    bsdf_pdf = Some(phase(scattered));
+                    ray = Ray::new(p, scattered);
+                    vertex = p;
+                    continue;
+                }
+            }
+
+            if matches!(hit.hit, HitType::Outside | HitType::BBox(_)) {
+                // The ray left the grid, weight the background against its light sample
+                let background = self.background(scene);
+                let weight = match bsdf_pdf {
+                    Some(bsdf_pdf) => power_heuristic(bsdf_pdf, background.pdf(ray.dir)),
+                    None => 1.0,
+                };
+                acc += clamp(background.radiance(ray.dir) * weight * mask, &bounces);
+                break;
+            }
+
+            let HitType::Voxel(m) = hit.hit else {
+                break;
+            };
+            let material = palette.get(m);
+
+            let x = hit.hitpoint;
+            let n = hit.normal;
+
+            // No normal, the ray started inside a voxel
+            if n == Vec3::zero() {
+                break;
+            }
+
+            let nl = if n.dot(ray.dir) < 0.0 { n } else { -n };
+            let lift = SURFACE_OFFSET / grid.density_f;
+
+            // The ray leaves the transmissive voxels it travelled through
+            let leaving = medium == Some(m);
+
+            // Emission, weighted against the emissive voxel sample of the last vertex
+            let emission = material.emission_color * material.emission_strength;
+            if !leaving && emission != Vec3::zero() {
+                let weight = match bsdf_pdf {
+                    Some(bsdf_pdf) => {
+                        let d = grid.density as i32;
+                        let voxel = Vec3::new(
+                            hit.tile_key.0 * d + hit.local_key.0,
+                            hit.tile_key.1 * d + hit.local_key.1,
+                            hit.tile_key.2 * d + hit.local_key.2,
+                        );
+                        let light_pdf = scene.emitters.pdf(voxel, vertex, x);
+                        power_heuristic(bsdf_pdf, light_pdf)
+                    }
+                    None => 1.0,
+                };
+                acc += clamp(mask * emission * weight, &bounces);
+            }
 
-        // if let Some(hit) = hit {
-        //     let mat = palette.get(hit.material);
-        //     acc.x = mat.base_color.x;
-        //     acc.y = mat.base_color.y;
-        //     acc.z = mat.base_color.z;
-        // }
-        //
-        for i in 0..4 {
-            let hit = grid.dda(&ray);
+            let bsdf = Bsdf::new(material, nl, -ray.dir, leaving);
+
+            // Direct lighting, lights are not seen through the interface from inside
+            if !leaving {
+                let light = self.sample_lights(
+                    scene,
+                    grid,
+                    palette,
+                    x + nl * lift,
+                    None,
+                    &mut rng,
+                    |l| bsdf.eval(l),
+                    |l| bsdf.pdf(l),
+                );
+                acc += clamp(mask * light, &bounces);
+            }
 
-            if hit.hit == HitType::Outside {
-                acc += self.srgb_to_linear(self.background_color) * mask;
+            let Some(sample) = bsdf.sample(self, &mut rng) else {
                 break;
-            } else if matches!(hit.hit, HitType::BBox(_)) {
-                if i == 0 {
-                    acc += (self.srgb_to_linear(self.background_color) + Vec3::new(0.2, 0.2, 0.2))
-                        * mask;
+            };
+            mask *= sample.weight;
+
+            if !bounces.add(sample.scatter, settings)
+                || !self.roulette(settings, &bounces, &mut mask, &mut rng)
+            {
+                break;
+            }
+
+            let transmitted = sample.direction.dot(nl) < 0.0;
+            let side = if transmitted { -nl } else { nl };
+            ray = Ray::new(x + side * lift, sample.direction);
+
+            // Passing straight into or out of an index matched volume is not a scattering
+            // event, the light pdfs stay relative to the last vertex. Without a light sample
+            // at a leaving vertex the next light hit carries all of its energy
+            if !(transmitted && material.is_index_matched()) {
+                bsdf_pdf = if leaving { None } else { sample.pdf };
+                vertex = ray.origin;
+            }
+
+            if transmitted {
+                if material.thin_walled {
+                    // Thin sheets are passed in one step, continue behind the voxels
+                    let exit = grid.exit(&ray, m);
+                    ray = Ray::new(exit.hitpoint + exit.normal * lift, sample.direction);
                 } else {
-                    acc += self.srgb_to_linear(self.background_color) * mask;
+                    medium = if leaving { None } else { Some(m) };
                 }
-                break;
             }
+        }
 
-            if let HitType::Voxel(m) = hit.hit {
-                let material = palette.get(m);
-                let albedo = material.base_color_linear();
-
-                let x = hit.hitpoint;
-                let n = hit.normal;
-                let nl = if n.dot(ray.dir) < 0.0 { n } else { -n };
-                let eps = 0.001;
-
-                let reflection_type = ReflectionType::GGX;
-
-                acc += mask
-                    * (material.base_color_linear() * Vec3::broadcast(1.0)
-                        + material.emission_color);
-
-                #[allow(clippy::single_match)]
-                match reflection_type {
-                    ReflectionType::Diffuse => {
-                        let r2: F = rng.random();
-                        let phi = 2.0 * std::f32::consts::PI * rng.random::<F>();
-                        let d = Self::jitter(nl, phi, r2.sqrt(), (1.0 - r2).sqrt());
-
-                        // Direct lighting sample
-                        let mut e = Vec3::zero();
-
-                        /*
-                        for light in &ft.graph.lights {
-                            let l0 = light.position() - x;
-                            let cos_a_max =
-                                ((1.0 - (light.radius() * light.radius() / l0.dot(l0))).max(0.0))
-                                    .sqrt();
-                            let cosa = lerp(cos_a_max, 1.0, rng.random());
-                            let l = Self::jitter(
-                                l0,
-                                2.0 * std::f32::consts::PI * rng.random::<F>(),
-                                (1.0 - cosa * cosa).sqrt(),
-                                cosa,
-                            );
-
-                            let shadow_origin = x + nl * 1e-3;
-                            let light_ray = Ray::new(shadow_origin, l);
-                            if light_ray
-                                .intersect_sphere(light.position(), light.radius())
-                                .is_some()
-                            {
-                                let omega = 2.0 * std::f32::consts::PI * (1.0 - cos_a_max);
-                                e += light.color() * n.dot(l).max(0.0) * omega / F_PI;
-                            }
-                        }*/
-
-                        acc += mask * material.emission_color + mask * albedo * e;
-                        mask *= albedo;
-                        ray = Ray::new(x, d).advanced(eps);
+        acc *= self.exposure.exp2();
+        Vec4::new(acc.x, acc.y, acc.z, 1.0)
+    }
+}
+
+impl PBR {
+    /// Russian roulette, randomly terminates paths after `roulette_depth` bounces with a
+    /// probability based on their throughput. Returns false if the path ends, otherwise the
+    /// throughput is compensated.
+    fn roulette<R: Rng>(
+        &self,
+        settings: &RenderSettings,
+        bounces: &Bounces,
+        mask: &mut Vec3<F>,
+        rng: &mut R,
+    ) -> bool {
+        if bounces.total <= settings.roulette_depth {
+            return true;
+        }
+        let survive = mask.reduce_partial_max().min(0.95);
+        if survive <= 0.0 || rng.random::<F>() >= survive {
+            return false;
+        }
+        *mask /= survive;
+        true
+    }
+
+    /// Next event estimation: sample every light of the scene, the background and one
+    /// emissive voxel from `origin` and return the unoccluded contribution. `eval` returns
+    /// the BSDF (or phase function) times the cosine for a light direction, `pdf` the pdf of
+    /// its sampling for the MIS weights. `medium` is the material of the volume `origin`
+    /// lies in.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_lights<R: Rng, E: Fn(Vec3<F>) -> Vec3<F>, P: Fn(Vec3<F>) -> F>(
+        &self,
+        scene: &Scene,
+        grid: &VoxelGrid,
+        palette: &Palette,
+        origin: Vec3<F>,
+        medium: Option<u8>,
+        rng: &mut R,
+        eval: E,
+        pdf: P,
+    ) -> Vec3<F> {
+        let mut e = Vec3::zero();
+
+        let background = self.background(scene);
+        let sun = background.sun();
+
+        for light in scene.lights.iter().chain(&sun) {
+            let Some(sample) = light.sample(origin, Vec2::new(rng.random(), rng.random())) else {
+                continue;
+            };
+            if sample.pdf <= 0.0 {
+                continue;
+            }
+
+            let f = eval(sample.direction);
+            if f == Vec3::zero() {
+                continue;
+            }
+
+            // Scene lights are never hit by BSDF samples, let them shine through refracting
+            // voxels unbent so water and glass are not left in the dark
+            let shadow_ray = Ray::new(origin, sample.direction);
+            let transmittance = self.transmittance(
+                scene,
+                grid,
+                palette,
+                &shadow_ray,
+                sample.distance,
+                medium,
+                true,
+            );
+            e += f * transmittance * sample.radiance / sample.pdf;
+        }
+
+        // The background and emissive voxels can also be hit by BSDF samples, combine both
+        // strategies with MIS
+        if let Some(sample) = background.sample(Vec2::new(rng.random(), rng.random())) {
+            let f = eval(sample.direction);
+            if f != Vec3::zero() {
+                let shadow_ray = Ray::new(origin, sample.direction);
+                let transmittance = self.transmittance(
+                    scene,
+                    grid,
+                    palette,
+                    &shadow_ray,
+                    sample.distance,
+                    medium,
+                    false,
+                );
+                let weight = power_heuristic(sample.pdf, pdf(sample.direction));
+                e += f * transmittance * sample.radiance * weight / sample.pdf;
+            }
+        }
+
+        // One emissive voxel
+        if !scene.emitters.is_empty()
+            && let Some(sample) = scene
+                .emitters
+                .sample(origin, Vec3::new(rng.random(), rng.random(), rng.random()))
+        {
+            let f = eval(sample.direction);
+
+            // The shadow ray ends on the surface of the emissive voxel
+            if f != Vec3::zero() {
+                let shadow_ray = Ray::new(origin, sample.direction);
+                let transmittance = self.transmittance(
+                    scene,
+                    grid,
+                    palette,
+                    &shadow_ray,
+                    sample.distance - 0.1 / grid.density_f,
+                    medium,
+                    false,
+                );
+                let weight = power_heuristic(sample.pdf, pdf(sample.direction));
+                e += f * transmittance * sample.radiance * weight / sample.pdf;
+            }
+        }
+
+        e
+    }
+
+    /// Ray march the fog along the ray up to `distance`. Returns the transmittance and the
+    /// light scattered towards the ray origin, every step samples the lights through the
+    /// phase function.
+    #[allow(clippy::too_many_arguments)]
+    fn march_fog<R: Rng>(
+        &self,
+        scene: &Scene,
+        grid: &VoxelGrid,
+        palette: &Palette,
+        settings: &RenderSettings,
+        fog: &Fog,
+        ray: &Ray,
+        distance: F,
+        rng: &mut R,
+    ) -> (F, Vec3<F>) {
+        let Some((start, end)) = Fog::segment(ray, &grid.bbox(), 0.0, distance) else {
+            return (1.0, Vec3::zero());
+        };
+
+        let background = self.background(scene);
+        let albedo = fog.albedo();
+        let steps = settings.fog_steps.max(1);
+        let step = (end - start) / steps as F;
+        let jitter: F = rng.random();
+
+        let mut transmittance = 1.0;
+        let mut inscattered = Vec3::zero();
+
+        for i in 0..steps {
+            let p = ray.at(start + (i as F + jitter) * step);
+            let extinction = fog.extinction(p);
+            if extinction <= 0.0 {
+                continue;
+            }
+            let phase = |l: Vec3<F>| fog.phase(ray.dir.dot(l));
+
+            // Nothing samples the phase function here, so the light samples take full weight
+            let mut light = self.sample_lights(
+                scene,
+                grid,
+                palette,
+                p,
+                None,
+                rng,
+                |l| Vec3::broadcast(phase(l)),
+                |_| 0.0,
+            );
+
+            // Backgrounds which are not importance sampled are sampled uniformly
+            let z = 1.0 - 2.0 * rng.random::<F>();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let angle = crate::F_TAU * rng.random::<F>();
+            let dir = Vec3::new(r * angle.cos(), r * angle.sin(), z);
+            if background.pdf(dir) <= 0.0 {
+                let radiance = background.radiance(dir);
+                if radiance != Vec3::zero() {
+                    let shadow_ray = Ray::new(p, dir);
+                    let visible =
+                        self.transmittance(scene, grid, palette, &shadow_ray, F::MAX, None, false);
+                    light += radiance * visible * (phase(dir) * 4.0 * crate::F_PI);
+                }
+            }
+
+            let absorbed = 1.0 - (-extinction * step).exp();
+            inscattered += light * albedo * (transmittance * absorbed);
+            transmittance *= 1.0 - absorbed;
+        }
+
+        (transmittance, inscattered)
+    }
+
+    /// The transmittance of a shadow ray up to `max_distance`. Opaque voxels block it,
+    /// index matched transmissive voxels let it pass, attenuated by their volume. Refracting
+    /// voxels only pass it if `refracting` is set, otherwise the light has to be found by
+    /// BSDF sampling through them. The fog of the scene attenuates the whole ray.
+    #[allow(clippy::too_many_arguments)]
+    fn transmittance(
+        &self,
+        scene: &Scene,
+        grid: &VoxelGrid,
+        palette: &Palette,
+        ray: &Ray,
+        max_distance: F,
+        mut medium: Option<u8>,
+        refracting: bool,
+    ) -> Vec3<F> {
+        let lift = SURFACE_OFFSET / grid.density_f;
+        let mut transmittance = match &scene.fog {
+            Some(fog) => Vec3::broadcast(fog.transmittance(ray, &grid.bbox(), max_distance)),
+            None => Vec3::one(),
+        };
+        let mut ray = *ray;
+        let mut travelled = 0.0;
+
+        // Bounded, every step enters or leaves a run of voxels
+        for _ in 0..16 {
+            match medium {
+                Some(m) => {
+                    let material = palette.get(m);
+                    let exit = grid.exit(&ray, m);
+                    let distance = (exit.hitpoint - ray.origin)
+                        .magnitude()
+                        .min(max_distance - travelled);
+                    if let Some(volume) = Medium::from_material(material) {
+                        transmittance *= volume.transmittance(distance);
                     }
-                    ReflectionType::Specular => {
-                        acc += mask * material.emission_color;
-                        mask *= material.base_color_linear();
-                        ray = Ray::new(x, ray.dir - 2.0 * ray.dir.dot(n) * n).advanced(eps);
+                    travelled += distance;
+
+                    if travelled >= max_distance {
+                        return transmittance;
                     }
-                    ReflectionType::Refractive => {
-                        let a = n.dot(ray.dir);
-                        let ddn = a.abs();
-                        let (nc, nt) = (1.0, material.ior);
-                        let into = a < 0.0;
-                        let nnt = if into { nc / nt } else { nt / nc };
-                        let cos2t = 1.0 - nnt * nnt * (1.0 - ddn * ddn);
-
-                        ray = Ray::new(x, ray.dir - 2.0 * ray.dir.dot(n) * n).advanced(eps);
-
-                        if cos2t > 0.0 {
-                            let tdir = (ray.dir * nnt
-                                + n * ((if into { 1.0 } else { -1.0 })
-                                    * (ddn * nnt + cos2t.sqrt())))
-                            .normalized();
-                            let r0 = ((nt - nc) / (nt + nc)).powi(2);
-                            let c = 1.0 - if into { -ray.dir.dot(n) } else { tdir.dot(n) };
-                            let re = r0 + (1.0 - r0) * c.powi(5);
-                            let p = 0.25 + 0.5 * re;
-                            let rp = re / p;
-                            let tp = (1.0 - re) / (1.0 - p);
-
-                            if rng.random::<F>() < p {
-                                mask *= rp;
-                            } else {
-                                mask *= albedo * tp;
-                                ray = Ray::new(x, tdir).advanced(eps);
-                            }
-                        }
+                    // Leave the voxels into empty space, or hit an opaque voxel inside them
+                    let passes = refracting || material.is_index_matched();
+                    match exit.hit {
+                        HitType::Outside if passes => return transmittance,
+                        HitType::Voxel(next) if next == m && passes => {}
+                        _ => return Vec3::zero(),
                     }
-                    ReflectionType::GGX => {
-                        let roughness = material.roughness.clamp(0.001, 1.0);
-                        let alpha = roughness * roughness;
-                        let metallic = material.metallic;
-                        let reflectance = 1.0;
-                        let base_f0 = 0.04;
-                        let f0 = base_f0 + (1.0 - base_f0) * metallic; // 0.04 for dielectrics → 1.0 for metal
-                        let v = (-ray.dir).normalized();
-                        let nv = n.dot(v).max(1e-5);
-                        let color = albedo;
-
-                        if rng.random::<F>() < reflectance {
-                            // GGX
-                            let mut brdf = Vec3::zero();
-
-                            /*
-                            for light in &ft.graph.lights {
-                                let l0 = light.position() - x;
-                                let cos_a_max = ((1.0
-                                    - (light.radius() * light.radius() / l0.dot(l0)))
-                                .max(0.0))
-                                .sqrt();
-                                let cosa = lerp(cos_a_max, 1.0, rng.random::<F>());
-
-                                let l = Self::jitter(
-                                    l0,
-                                    2.0 * F_PI * rng.random::<F>(),
-                                    (1.0 - cosa * cosa).sqrt(),
-                                    cosa,
-                                )
-                                .normalized();
-
-                                let shadow_origin = x + nl * eps;
-                                if Ray::new(shadow_origin, l)
-                                    .intersect_sphere(light.position(), light.radius())
-                                    .is_some()
-                                {
-                                    let omega = 2.0 * F_PI * (1.0 - cos_a_max);
-                                    let g = Self::ggx(nl, v, l, roughness, f0).clamp(0.0, 1.0);
-                                    brdf += light.color() * g * omega / nv; // F_PI;
-                                }
-                            }*/
-
-                            let xsi_1: F = rng.random();
-                            let xsi_2: F = rng.random();
-                            let phi = ((alpha * xsi_1.sqrt()) / (1.0 - xsi_1).sqrt()).atan();
-                            let theta = 2.0 * F_PI * xsi_2;
-                            let dir = Self::angle_to_dir(nl, theta, phi);
-
-                            acc += mask * material.emission_color + mask * color * brdf;
-                            mask *= color;
-                            ray = Ray::new(x, dir).advanced(eps);
-                        } else {
-                            // ── diffuse fallback
-                            let r2: F = rng.random();
-                            let d = Self::jitter(
-                                nl,
-                                2.0 * F_PI * rng.random::<F>(),
-                                r2.sqrt(),
-                                (1.0 - r2).sqrt(),
-                            );
-
-                            let mut e = Vec3::zero();
-                            /*
-                            for light in &ft.graph.lights {
-                                let l0 = light.position() - x;
-                                let cos_a_max = ((1.0
-                                    - (light.radius() * light.radius() / l0.dot(l0)))
-                                .max(0.0))
-                                .sqrt();
-                                let cosa = lerp(cos_a_max, 1.0, rng.random::<F>());
-                                let l = Self::jitter(
-                                    l0,
-                                    2.0 * F_PI * rng.random::<F>(),
-                                    (1.0 - cosa * cosa).sqrt(),
-                                    cosa,
-                                )
-                                .normalized();
-
-                                let shadow_origin = x + nl * eps;
-                                if Ray::new(shadow_origin, l)
-                                    .intersect_sphere(light.position(), light.radius())
-                                    .is_some()
-                                {
-                                    let omega = 2.0 * F_PI * (1.0 - cos_a_max);
-                                    e += light.color() * l.dot(n).max(0.0) * omega / F_PI;
-                                }
-                            }*/
-
-                            acc += mask * material.emission_color + mask * color * e;
-                            mask *= color;
-                            ray = Ray::new(x, d).advanced(eps);
-                        }
+
+                    medium = None;
+                    ray = Ray::new(exit.hitpoint + exit.normal * lift, ray.dir);
+                    travelled += lift;
+                }
+                None => {
+                    let hit = grid.dda(&ray);
+                    let HitType::Voxel(m) = hit.hit else {
+                        return transmittance;
+                    };
+                    let distance = (hit.hitpoint - ray.origin).magnitude();
+                    if travelled + distance >= max_distance {
+                        return transmittance;
+                    }
+
+                    let material = palette.get(m);
+                    if !(refracting || material.is_index_matched()) {
+                        return Vec3::zero();
+                    }
+
+                    // The share of the light passing the surface
+                    let pass = (1.0 - material.metallic) * material.transmission;
+                    transmittance *= if material.has_volume() {
+                        Vec3::broadcast(pass)
+                    } else {
+                        material.base_color_linear() * pass
+                    };
+                    if transmittance == Vec3::zero() {
+                        return transmittance;
                     }
+
+                    medium = Some(m);
+                    ray = Ray::new(hit.hitpoint + ray.dir * lift, ray.dir);
+                    travelled += distance + lift;
                 }
             }
         }
 
-        Vec4::new(acc.x, acc.y, acc.z, 1.0)
+        transmittance
     }
 }
 
@@ -326,3 +588,11 @@ impl PBRTrait for PBR {
         (u * theta.cos() + v * theta.sin()) * sin_phi + w * cos_phi
     }
 }
+
+/// The power heuristic (beta = 2) weight of a sample with pdf `a` against the pdf `b` of the
+/// other strategy.
+fn power_heuristic(a: F, b: F) -> F {
+    let a2 = a * a;
+    let b2 = b * b;
+    if a2 + b2 > 0.0 { a2 / (a2 + b2) } else { 0.0 }
+}
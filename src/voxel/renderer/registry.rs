@@ -0,0 +1,70 @@
+use crate::prelude::*;
+use std::sync::Arc;
+
+/// The available renderers keyed by their name, one of them is active. Renderers keep their
+/// parameters while another one is active.
+pub struct RendererRegistry {
+    renderers: Vec<Arc<Box<dyn Renderer>>>,
+    active: usize,
+}
+
+impl Default for RendererRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RendererRegistry {
+    /// The built-in renderers, the path tracer is active.
+    pub fn new() -> Self {
+        let mut registry = Self {
+            renderers: vec![],
+            active: 0,
+        };
+        registry.register(Box::new(PBR::new()));
+        registry.register(Box::new(EditShader::new()));
+        registry
+    }
+
+    /// Add a renderer, replaces a registered renderer of the same name.
+    pub fn register(&mut self, renderer: Box<dyn Renderer>) {
+        let renderer = Arc::new(renderer);
+        if let Some(index) = self.index(renderer.name()) {
+            self.renderers[index] = renderer;
+        } else {
+            self.renderers.push(renderer);
+        }
+    }
+
+    /// The names of the registered renderers in registration order.
+    pub fn names(&self) -> Vec<String> {
+        self.renderers
+            .iter()
+            .map(|r| r.name().to_string())
+            .collect()
+    }
+
+    /// The active renderer.
+    pub fn active(&self) -> Arc<Box<dyn Renderer>> {
+        Arc::clone(&self.renderers[self.active])
+    }
+
+    /// Activate the renderer of the given name, returns false if there is none.
+    pub fn set_active(&mut self, name: &str) -> bool {
+        if let Some(index) = self.index(name) {
+            self.active = index;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Set a parameter of the active renderer. Renders still running keep the old state.
+    pub fn set_param(&mut self, id: &str, value: F) {
+        Arc::make_mut(&mut self.renderers[self.active]).set_param(id, value);
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.renderers.iter().position(|r| r.name() == name)
+    }
+}
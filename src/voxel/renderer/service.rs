@@ -0,0 +1,202 @@
+use crate::prelude::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use vek::Vec2;
+
+/// A rectangle of the frame rendered with one sample per pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderTile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// The index of the first sample rendered for the tile.
+    pub sample: u32,
+    /// The number of samples to render, every sample is a job of its own.
+    pub count: u32,
+}
+
+/// The immutable state a pass is rendered with, taken when the pass is submitted. The
+/// workers never lock anything of the editor, edits copy what they change and leave the
+/// snapshot untouched, so a pass never sees a half applied edit.
+pub struct RenderSnapshot {
+    pub grid: Arc<VoxelGrid>,
+    pub palette: Arc<Palette>,
+    pub scene: Arc<Scene>,
+    pub renderer: Arc<Box<dyn Renderer>>,
+    pub camera: Box<dyn Camera>,
+    pub settings: RenderSettings,
+    /// The size of the frame in pixels.
+    pub width: usize,
+    pub height: usize,
+}
+
+/// One sample of a finished tile.
+pub struct TileResult {
+    pub generation: u64,
+    pub tile: RenderTile,
+    pub buffer: RenderBuffer,
+}
+
+struct Job {
+    generation: u64,
+    tile: RenderTile,
+    sample: u32,
+    snapshot: Arc<RenderSnapshot>,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<Job>>,
+    available: Condvar,
+    generation: AtomicU64,
+}
+
+/// A pool of long-lived render workers. Tiles are submitted for the current generation,
+/// `cancel` starts a new one which drops all queued jobs and makes the workers abandon the
+/// tiles they are working on. Finished tiles are collected with `finished`, the caller owns
+/// the accumulation, so nothing here ever locks its render buffer.
+pub struct RenderService {
+    shared: Arc<Shared>,
+    results: Mutex<Receiver<TileResult>>,
+}
+
+impl RenderService {
+    /// Start the given number of workers.
+    pub fn new(workers: usize) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            generation: AtomicU64::new(0),
+        });
+        let (sender, results) = channel();
+
+        for _ in 0..workers.max(1) {
+            let shared = Arc::clone(&shared);
+            let sender = sender.clone();
+            thread::spawn(move || Self::work(&shared, &sender));
+        }
+
+        Self {
+            shared,
+            results: Mutex::new(results),
+        }
+    }
+
+    /// The current generation, results of older ones are dropped.
+    pub fn generation(&self) -> u64 {
+        self.shared.generation.load(Ordering::Acquire)
+    }
+
+    /// Cancel all queued and running tiles.
+    pub fn cancel(&self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        self.shared.generation.fetch_add(1, Ordering::AcqRel);
+        queue.clear();
+    }
+
+    /// Queue every sample of the tiles for the current generation, returns the number of
+    /// results to expect.
+    pub fn submit(&self, snapshot: Arc<RenderSnapshot>, tiles: Vec<RenderTile>) -> usize {
+        let generation = self.generation();
+        let mut queue = self.shared.queue.lock().unwrap();
+        let mut jobs = 0;
+        for tile in tiles {
+            for sample in tile.sample..tile.sample + tile.count {
+                queue.push_back(Job {
+                    generation,
+                    tile,
+                    sample,
+                    snapshot: Arc::clone(&snapshot),
+                });
+                jobs += 1;
+            }
+        }
+        self.shared.available.notify_all();
+        jobs
+    }
+
+    /// The tiles of the current generation finished since the last call, never blocks.
+    pub fn finished(&self) -> Vec<TileResult> {
+        let generation = self.generation();
+        let results = self.results.lock().unwrap();
+        results
+            .try_iter()
+            .filter(|result| result.generation == generation)
+            .collect()
+    }
+
+    /// The loop of a worker.
+    fn work(shared: &Shared, sender: &Sender<TileResult>) {
+        loop {
+            let job = {
+                let mut queue = shared.queue.lock().unwrap();
+                loop {
+                    if let Some(job) = queue.pop_front() {
+                        break job;
+                    }
+                    queue = shared.available.wait(queue).unwrap();
+                }
+            };
+
+            if let Some(buffer) = Self::render(shared, &job)
+                && sender
+                    .send(TileResult {
+                        generation: job.generation,
+                        tile: job.tile,
+                        buffer,
+                    })
+                    .is_err()
+            {
+                // The service is gone
+                return;
+            }
+        }
+    }
+
+    /// Render one sample of the tile, None if the generation was cancelled in between.
+    fn render(shared: &Shared, job: &Job) -> Option<RenderBuffer> {
+        let snapshot = &job.snapshot;
+        let tile = &job.tile;
+        let resolution = Vec2::new(snapshot.width as F, snapshot.height as F);
+        let mut buffer = RenderBuffer::new(tile.width, tile.height);
+
+        for h in 0..tile.height {
+            if shared.generation.load(Ordering::Acquire) != job.generation {
+                return None;
+            }
+
+            let y = tile.y + h;
+            if y >= snapshot.height {
+                break;
+            }
+
+            for w in 0..tile.width {
+                let x = tile.x + w;
+                if x >= snapshot.width {
+                    break;
+                }
+
+                let uv = Vec2::new(x as F / resolution.x, 1.0 - (y as F / resolution.y));
+                let mut aov = AovSample::default();
+                let p = snapshot.renderer.render(
+                    uv,
+                    resolution,
+                    &snapshot.grid,
+                    &snapshot.palette,
+                    &snapshot.scene,
+                    snapshot.camera.as_ref(),
+                    &snapshot.settings,
+                    job.sample,
+                    &mut aov,
+                );
+                buffer.set(w, h, p.into_array());
+                buffer.set_aov(w, h, &aov);
+            }
+        }
+
+        Some(buffer)
+    }
+}
@@ -0,0 +1,124 @@
+use crate::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use vek::{Vec2, Vec3};
+
+/// The kind of a scattering event along a path, each kind has its own bounce limit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scatter {
+    Diffuse,
+    Glossy,
+    Transmission,
+    Volume,
+}
+
+/// The quality settings of a render, stored with the project and passed to every renderer.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// The maximum number of bounces of a path, 0 only renders direct lighting.
+    pub max_bounces: u32,
+    pub max_diffuse_bounces: u32,
+    pub max_glossy_bounces: u32,
+    /// Entering and leaving transmissive voxels both count as a bounce.
+    pub max_transmission_bounces: u32,
+    pub max_volume_bounces: u32,
+    /// The number of bounces after which paths are terminated by Russian roulette.
+    pub roulette_depth: u32,
+    /// The maximum value of a channel of the light gathered after the first bounce, trades
+    /// fireflies for bias. 0 disables the clamp.
+    pub clamp_indirect: F,
+    /// The number of samples per pixel after which the accumulation stops, 0 never stops.
+    pub samples: u32,
+    /// The noise of a pixel (its standard error relative to the square root of its
+    /// brightness) below which it counts as converged. Tiles with converged pixels stop
+    /// sampling early, noisy tiles get more samples per pass. 0 disables adaptive sampling.
+    pub noise_threshold: F,
+    /// The number of samples every pixel gets before its noise is estimated.
+    pub min_samples: u32,
+    /// The seed of the random numbers, renders with the same seed are reproducible.
+    pub seed: u32,
+    /// The number of ray marching steps through the fog per path segment.
+    pub fog_steps: u32,
+    /// Denoise the accumulated frame in the viewport and on export.
+    pub denoise: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            max_bounces: 6,
+            max_diffuse_bounces: 3,
+            max_glossy_bounces: 4,
+            max_transmission_bounces: 8,
+            max_volume_bounces: 3,
+            roulette_depth: 3,
+            clamp_indirect: 10.0,
+            samples: 256,
+            noise_threshold: 0.01,
+            min_samples: 16,
+            seed: 0,
+            fog_steps: 8,
+            denoise: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The random number generator for a sample of the pixel at `uv`, derived from the seed.
+    pub fn rng(&self, uv: Vec2<F>, resolution: Vec2<F>, sample: u32) -> SmallRng {
+        let x = (uv.x * resolution.x).max(0.0) as u64;
+        let y = ((1.0 - uv.y) * resolution.y).max(0.0) as u64;
+        let key = (self.seed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ x.wrapping_mul(0xbf58_476d_1ce4_e5b9)
+            ^ y.wrapping_mul(0x94d0_49bb_1331_11eb)
+            ^ (sample as u64).wrapping_mul(0xd6e8_feb8_6659_fd93);
+        SmallRng::seed_from_u64(key)
+    }
+
+    /// Clamp the light gathered after the first bounce to `clamp_indirect`, keeping its hue.
+    pub fn clamp(&self, radiance: Vec3<F>) -> Vec3<F> {
+        let max = radiance.reduce_partial_max();
+        if self.clamp_indirect > 0.0 && max > self.clamp_indirect {
+            radiance * (self.clamp_indirect / max)
+        } else {
+            radiance
+        }
+    }
+}
+
+/// The bounces of a path so far, in total and per kind of scattering.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Bounces {
+    pub total: u32,
+    pub diffuse: u32,
+    pub glossy: u32,
+    pub transmission: u32,
+    pub volume: u32,
+}
+
+impl Bounces {
+    /// Count a bounce, returns false if the path exceeds one of the limits of the settings.
+    pub fn add(&mut self, scatter: Scatter, settings: &RenderSettings) -> bool {
+        self.total += 1;
+        let (count, max) = match scatter {
+            Scatter::Diffuse => {
+                self.diffuse += 1;
+                (self.diffuse, settings.max_diffuse_bounces)
+            }
+            Scatter::Glossy => {
+                self.glossy += 1;
+                (self.glossy, settings.max_glossy_bounces)
+            }
+            Scatter::Transmission => {
+                self.transmission += 1;
+                (self.transmission, settings.max_transmission_bounces)
+            }
+            Scatter::Volume => {
+                self.volume += 1;
+                (self.volume, settings.max_volume_bounces)
+            }
+        };
+        self.total <= settings.max_bounces && count <= max
+    }
+}
@@ -0,0 +1,49 @@
+use crate::prelude::*;
+
+/// Scene wide render and export settings which are not part of the voxel grid, stored with
+/// the project.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Scene {
+    /// The lights sampled by the renderer.
+    #[serde(default)]
+    pub lights: Vec<Light>,
+
+    /// The background seen by rays leaving the grid.
+    #[serde(default)]
+    pub background: Background,
+
+    /// Height fog and atmosphere filling the grid bounds.
+    #[serde(default)]
+    pub fog: Option<Fog>,
+
+    /// The physical size of one voxel in millimetres, the scale of STL exports.
+    #[serde(default = "Scene::default_voxel_size_mm")]
+    pub voxel_size_mm: F,
+
+    /// The emissive voxels of the grid, rebuilt by the renderer when the scene changes.
+    #[serde(skip)]
+    pub emitters: Emitters,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    /// A scene without lights, only lit by the default background.
+    pub fn new() -> Self {
+        Self {
+            lights: vec![],
+            background: Background::default(),
+            fog: None,
+            voxel_size_mm: Self::default_voxel_size_mm(),
+            emitters: Emitters::default(),
+        }
+    }
+
+    fn default_voxel_size_mm() -> F {
+        crate::voxel::io::stl::DEFAULT_VOXEL_SIZE_MM
+    }
+}
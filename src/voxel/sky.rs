@@ -0,0 +1,166 @@
+use crate::F_PI;
+use crate::prelude::*;
+use vek::Vec3;
+
+/// Scales the sky luminance of the Preetham model (kcd/m²) into the radiance range of the
+/// scene lights, a clear noon sky ends up at about a tenth of the sun irradiance.
+const SKY_SCALE: F = 0.025;
+
+/// The angular radius of the sun disc in radians, matches the default scene sun.
+const SUN_ANGLE: F = 0.02;
+
+/// The analytic daylight model of Preetham, Shirley and Smits, paired with a directional sun.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Sky {
+    /// Sun elevation above the horizon in radians.
+    pub elevation: F,
+    /// Sun azimuth in radians, 0 is towards -z, increasing towards +x.
+    pub azimuth: F,
+    /// Atmospheric turbidity, 2 is a clear sky, 10 is hazy.
+    pub turbidity: F,
+    /// Multiplier of the sky radiance.
+    pub intensity: F,
+    /// Irradiance of the sun before atmospheric extinction.
+    pub sun_intensity: F,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            elevation: 0.8,
+            azimuth: 0.9,
+            turbidity: 3.0,
+            intensity: 1.0,
+            sun_intensity: 3.5,
+        }
+    }
+}
+
+impl Sky {
+    /// The normalized direction towards the sun.
+    pub fn sun_direction(&self) -> Vec3<F> {
+        let elevation = self.elevation.clamp(-F_PI * 0.5, F_PI * 0.5);
+        Vec3::new(
+            elevation.cos() * self.azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * self.azimuth.cos(),
+        )
+    }
+
+    /// The directional light of the sun, tinted by the extinction along its path through the
+    /// atmosphere. None once the sun is below the horizon.
+    pub fn sun(&self) -> Option<Light> {
+        let direction = self.sun_direction();
+        if direction.y <= 0.0 {
+            return None;
+        }
+
+        // Relative optical air mass (Kasten and Young)
+        let zenith_deg = (F_PI * 0.5 - self.elevation).to_degrees();
+        let air_mass =
+            1.0 / (direction.y + 0.50572 * (96.07995 - zenith_deg).max(0.1).powf(-1.6364));
+
+        // Rayleigh and aerosol (Angstrom) optical depths for red, green and blue
+        let beta = (0.04608 * self.turbidity - 0.04586).max(0.0);
+        let color = Vec3::new(0.65, 0.55, 0.45).map(|lambda: F| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        });
+
+        Some(Light::new(
+            LightType::Directional {
+                direction,
+                angle: SUN_ANGLE,
+            },
+            color,
+            self.sun_intensity,
+        ))
+    }
+
+    /// The linear sky radiance in the given direction. Below the horizon a dim ground
+    /// reflecting the horizon is returned.
+    pub fn radiance(&self, dir: Vec3<F>) -> Vec3<F> {
+        let dir = dir.normalized();
+        let sun = self.sun_direction();
+
+        // The model is only valid for the sun above the horizon, fade out the twilight
+        let theta_s = (F_PI * 0.5 - self.elevation.max(0.0)).min(F_PI * 0.5 - 0.01);
+        let night = (self.elevation / 0.1 + 1.0).clamp(0.0, 1.0);
+
+        let ground = dir.y < 0.0;
+        let view = if ground {
+            Vec3::new(dir.x, 0.0, dir.z).normalized()
+        } else {
+            dir
+        };
+        let theta = view.y.clamp(0.001, 1.0).acos().min(F_PI * 0.5 - 0.001);
+        let gamma = view.dot(sun).clamp(-1.0, 1.0).acos();
+
+        let t = self.turbidity.clamp(1.7, 10.0);
+        let perez_y = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_yy = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        // Zenith luminance and chromaticity
+        let chi = (4.0 / 9.0 - t / 120.0) * (F_PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let (t3, t2) = (theta_s.powi(3), theta_s.powi(2));
+        let zenith_x = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * theta_s)
+            + t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * t3 - 0.21196 * t2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * theta_s)
+            + t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * t3 - 0.26756 * t2 + 0.06670 * theta_s + 0.26688);
+
+        let luminance =
+            zenith_luminance * perez(&perez_y, theta, gamma) / perez(&perez_y, 0.0, theta_s);
+        let x = zenith_x * perez(&perez_x, theta, gamma) / perez(&perez_x, 0.0, theta_s);
+        let y = zenith_y * perez(&perez_yy, theta, gamma) / perez(&perez_yy, 0.0, theta_s);
+
+        let mut color = xyy_to_linear_srgb(x, y, luminance) * (SKY_SCALE * self.intensity * night);
+        if ground {
+            color *= 0.3;
+        }
+        color.map(|c| c.max(0.0))
+    }
+}
+
+/// The Perez sky luminance distribution function.
+fn perez(c: &[F; 5], theta: F, gamma: F) -> F {
+    (1.0 + c[0] * (c[1] / theta.cos().max(0.01)).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+/// Convert CIE xyY to linear sRGB (D65).
+fn xyy_to_linear_srgb(x: F, y: F, luminance: F) -> Vec3<F> {
+    if y <= 0.0 {
+        return Vec3::zero();
+    }
+    let cx = x * luminance / y;
+    let cz = (1.0 - x - y) * luminance / y;
+    let cy = luminance;
+    Vec3::new(
+        3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+        -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+        0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+    )
+}
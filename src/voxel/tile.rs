@@ -53,6 +53,11 @@ impl Tile {
     }
 
     pub fn update_bbox(&mut self) {
+        (self.has_voxels, self.bbox) = self.compute_bbox();
+    }
+
+    /// Whether the tile has voxels and the bounding box of its voxels, in voxels.
+    pub fn compute_bbox(&self) -> (bool, Aabb<F>) {
         let mut min = Vec3::new(i32::MAX, i32::MAX, i32::MAX);
         let mut max = Vec3::new(i32::MIN, i32::MIN, i32::MIN);
         let d = self.density as i32;
@@ -75,18 +80,22 @@ impl Tile {
             }
         }
 
-        self.has_voxels = found;
-
         if found {
-            self.bbox = Aabb {
-                min: min.map(|v| v as F),
-                max: max.map(|v| v as F + 1.0),
-            };
+            (
+                true,
+                Aabb {
+                    min: min.map(|v| v as F),
+                    max: max.map(|v| v as F + 1.0),
+                },
+            )
         } else {
-            self.bbox = Aabb {
-                min: Vec3::zero(),
-                max: Vec3::zero(),
-            };
+            (
+                false,
+                Aabb {
+                    min: Vec3::zero(),
+                    max: Vec3::zero(),
+                },
+            )
         }
     }
 